@@ -1,11 +1,13 @@
-use clap::Parser;
-#[cfg(feature = "tls")]
-use clap::ValueHint;
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use clap::{Parser, ValueEnum, ValueHint};
 use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::StatusCode;
+use std::fmt;
 use std::net::IpAddr;
-#[cfg(feature = "tls")]
 use std::path::PathBuf;
 
+use crate::template::has_template_syntax;
+
 #[derive(Debug, Clone, Parser)]
 #[command(name = "dummyhttp", author, about, version)]
 pub struct Args {
@@ -18,30 +20,149 @@ pub struct Args {
     #[arg(short, long, action = clap::ArgAction::Count)]
     pub verbose: u8,
 
-    /// Port on which to listen
+    /// Format of the request log (headers and bodies are included with -v and -vv like in the
+    /// pretty log)
+    #[arg(long, value_enum, default_value = "pretty")]
+    pub log_format: LogFormat,
+
+    /// Port on which to listen (0 picks a free port)
     #[arg(short, long, default_value = "8080")]
     pub port: u16,
 
     /// Headers to send (format: key:value)
+    ///
+    /// Values are templates just like the body, which they can refer to as `body`, e.g. to sign
+    /// it: "X-Signature:{{ body | hmac_sha256(key="secret") }}"
     #[arg(short = 'H', long, value_parser(parse_header))]
     pub headers: Vec<HeaderMap>,
 
     /// HTTP status code to send
-    #[arg(short, long, default_value = "200")]
-    pub code: u16,
+    ///
+    /// Can be a template just like the body, e.g. to let the request pick it:
+    /// "{{ request.query.status | default(value=200) }}". The rendered body is available as `body`.
+    #[arg(short, long, default_value = "200", value_parser(parse_code))]
+    pub code: String,
+
+    /// HTTP status code to send when rendering a template fails
+    ///
+    /// The response body and the log explain what went wrong.
+    #[arg(long, default_value = "500", value_parser = clap::value_parser!(u16).range(100..1000))]
+    pub template_error_code: u16,
 
     /// HTTP body to send
     ///
     /// Supports Tera-based templating (https://tera.netlify.app/docs/) with a few additional
     /// functions over the default built-ins:
     ///
+    /// request.method, request.path, request.query.<name>, request.headers.<name>, request.body
+    ///     - the request being answered (header names are lowercase)
+    /// request.client, request.client_ip - the client's address (from the PROXY protocol header
+    ///     if there is one) and IP, which Unix socket clients don't have
     /// uuid() - generate a random UUID
     /// lorem(words) - generate `words` lorem ipsum words
+    /// env(name, default) - environment variable `name`, or `default` if it isn't set
+    /// file(path) - contents of the file at `path`
+    /// counter(name, per_path=false) - 1, 2, 3, ... for every call, counting separately for
+    ///     each `name` and, if `per_path`, each request path
+    ///
+    /// Templates in --templates-dir can be included and extended, their components used anywhere:
+    /// {% include "partials/user.json" %}
+    ///
+    /// Fake data:
+    ///
+    /// first_name(), last_name(), name(), username(), email(), phone()
+    /// address(), city(), country(), company()
+    /// ipv4(), ipv6(), url()
+    /// iban(country="DE") - IBAN for AT, BE, CH, DE, ES or PL
+    /// date_between(start, end, format) - date between YYYY-MM-DD or RFC 3339 dates
+    /// int(min=0, max=100), float(min=0, max=1), bool(probability=0.5)
+    /// pick(list) - random element of `list`
+    /// regex(pattern) - random string matching `pattern`
+    ///
+    /// jwt(claims, alg="HS256", key) - signed JWT, `key` being the secret for HS256 and a PEM key
+    ///     for RS256/ES256 (defaults to --jwt-key)
+    ///
+    /// Filters:
+    ///
+    /// base64_encode, base64_decode, base64url_encode, base64url_decode
+    /// hex_encode, hex_decode, urlencode, urldecode, json_escape
+    /// sha1, sha256, sha512 - hex digest, or base64(url) via encoding="base64"
+    /// hmac_sha1(key), hmac_sha256(key), hmac_sha512(key) - same for HMACs
     ///
     /// Example: dummyhttp -b "Hello {{ uuid() }}, it's {{ now() | date(format="%Y") }} {{ lorem(words=5)}}"
     #[arg(short, long, default_value = "dummyhttp", verbatim_doc_comment)]
     pub body: String,
 
+    /// Read the body template from this file instead
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath, conflicts_with = "body")]
+    pub body_file: Option<PathBuf>,
+
+    /// Directory of templates (and subdirectories) to include, extend or use components from
+    ///
+    /// Changes to the files are picked up while running.
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    pub templates_dir: Option<PathBuf>,
+
+    /// Send the template with this name in --templates-dir (e.g. users/list.json) as body
+    #[arg(
+        long,
+        value_name = "NAME",
+        requires = "templates_dir",
+        conflicts_with_all = ["body", "body_file"]
+    )]
+    pub body_template: Option<String>,
+
+    /// Seed for the random template functions
+    ///
+    /// Every request gets its own generator derived from the seed and the number of the request so
+    /// the nth request is answered with the same data on every run.
+    #[arg(long)]
+    pub seed: Option<u64>,
+
+    /// Pretend it's always this time (RFC 3339) for now(), the date header and the log
+    #[arg(long, value_name = "TIME", value_parser(parse_time))]
+    pub fixed_time: Option<DateTime<FixedOffset>>,
+
+    /// Shift the clock by this much (e.g. -1d, 2h30m) for now(), the date header and the log
+    #[arg(
+        long,
+        value_name = "OFFSET",
+        value_parser(parse_offset),
+        allow_hyphen_values = true,
+        conflicts_with = "fixed_time"
+    )]
+    pub clock_offset: Option<TimeDelta>,
+
+    /// Private key (PEM, RSA or P-256) jwt() signs RS256/ES256 tokens with by default
+    #[arg(long, value_name = "KEY", value_hint = ValueHint::FilePath)]
+    pub jwt_key: Option<PathBuf>,
+
+    /// Serve the public key of --jwt-key as JWK Set at this path (e.g. /.well-known/jwks.json)
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser(parse_path),
+        requires = "jwt_key"
+    )]
+    pub jwks_path: Option<String>,
+
+    /// Send a generated body of this size instead (e.g. 10MB, 1GiB), streamed without holding it
+    /// in memory
+    #[arg(long, value_name = "SIZE", value_parser(parse_size))]
+    pub body_size: Option<u64>,
+
+    /// What to fill bodies generated via --body-size with
+    #[arg(long, value_enum, default_value = "text", requires = "body_size")]
+    pub body_pattern: BodyPattern,
+
+    /// Seed for --body-pattern seeded
+    #[arg(long, default_value = "0", requires = "body_size")]
+    pub body_seed: u64,
+
+    /// Send bodies generated via --body-size chunked rather than with a Content-Length
+    #[arg(long, requires = "body_size")]
+    pub chunked: bool,
+
     /// Interface to bind to
     #[arg(
         short,
@@ -52,6 +173,88 @@ pub struct Args {
     )]
     pub interface: IpAddr,
 
+    /// Address to listen on (format: http://host:port, https://host:port or unix:path)
+    ///
+    /// Can be given multiple times to serve on several addresses at once. Replaces --interface
+    /// and --port. Host names are bound on all addresses they resolve to and `[::]` accepts both
+    /// IPv4 and IPv6 unless IPv4 is bound separately on the same port.
+    ///
+    /// Sockets passed via systemd socket activation (LISTEN_FDS) are used instead of --interface
+    /// and --port. Sockets named `https` or `tls` in LISTEN_FDNAMES serve TLS.
+    #[arg(long, value_parser(parse_listen), conflicts_with_all = ["interface", "port"])]
+    pub listen: Vec<ListenSpec>,
+
+    /// Permissions of Unix sockets given via --listen (format: octal, e.g. 660)
+    ///
+    /// Stale socket files left behind by a previous run are removed on startup.
+    #[arg(long, value_parser(parse_mode))]
+    pub unix_socket_mode: Option<u32>,
+
+    /// Only accept HTTP/1 (TLS listeners only offer http/1.1 via ALPN)
+    ///
+    /// h2c clients with prior knowledge are refused along with their connection, before there's a
+    /// request to log.
+    #[arg(long, conflicts_with = "http2_only")]
+    pub http1_only: bool,
+
+    /// Only accept HTTP/2 (plain listeners expect h2c with prior knowledge)
+    ///
+    /// HTTP/1 clients are refused along with their connection, so they don't show up in the log.
+    #[arg(long)]
+    pub http2_only: bool,
+
+    /// Reject HTTP/1.0 requests with 505 HTTP Version Not Supported
+    #[arg(long, conflicts_with = "http2_only")]
+    pub reject_http10: bool,
+
+    /// Expect HAProxy PROXY protocol (v1 or v2) headers on TCP connections and log the client
+    /// address they carry instead of the proxy's
+    #[arg(long, value_enum, value_name = "MODE")]
+    pub proxy_protocol: Option<ProxyProtocol>,
+
+    /// Write the bound addresses as JSON to this file once we're accepting connections
+    #[arg(long, value_hint = ValueHint::FilePath)]
+    pub ready_file: Option<PathBuf>,
+
+    /// Print the bound addresses as a JSON line instead of the startup banner
+    #[arg(long)]
+    pub print_ready_json: bool,
+
+    /// Reject requests with bodies larger than this with 413 Payload Too Large (e.g. 10MB, 1GiB)
+    #[arg(long, value_name = "SIZE", value_parser(parse_size))]
+    pub max_body_size: Option<u64>,
+
+    /// Only keep and log this much of each body, larger ones are counted and hashed instead
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser(parse_size),
+        default_value = "64KiB"
+    )]
+    pub log_body_limit: u64,
+
+    /// Only pass this much of each request body to templates as `request.body`
+    ///
+    /// Bodies are only kept around at all if a template refers to `request`.
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser(parse_size),
+        default_value = "1MiB"
+    )]
+    pub template_body_limit: u64,
+
+    /// Compress responses, either negotiating the encoding with the client's Accept-Encoding
+    /// (auto) or always using the given one
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ENCODING",
+        num_args = 0..=1,
+        default_missing_value = "auto"
+    )]
+    pub compress: Option<Compress>,
+
     /// Delay in milliseconds before sending the response in milliseconds
     #[arg(short, long, default_value = "0")]
     pub delay: u64,
@@ -65,14 +268,237 @@ pub struct Args {
     pub print_manpage: bool,
 
     /// TLS certificate to use
+    ///
+    /// Can be given multiple times as `hostname=cert.pem,key.pem` to select a certificate by the
+    /// SNI hostname requested by the client. A certificate given without a hostname uses the key
+    /// from --tls-key and is served when no hostname matches.
+    ///
+    /// Certificates are reloaded without a restart whenever their files change or on SIGHUP.
     #[cfg(feature = "tls")]
-    #[arg(long = "tls-cert", alias = "cert", requires = "tls_key", value_hint = ValueHint::FilePath)]
-    pub tls_cert: Option<PathBuf>,
+    #[arg(long = "tls-cert", alias = "cert", value_parser(parse_tls_cert), value_hint = ValueHint::FilePath)]
+    pub tls_cert: Vec<TlsCert>,
 
     /// TLS private key to use
     #[cfg(feature = "tls")]
     #[arg(long = "tls-key", alias = "key", requires = "tls_cert", value_hint = ValueHint::FilePath)]
     pub tls_key: Option<PathBuf>,
+
+    /// What to do when a client requests an SNI hostname without a matching certificate
+    #[cfg(feature = "tls")]
+    #[arg(long = "tls-unknown-sni", value_enum, default_value = "fallback")]
+    pub tls_unknown_sni: UnknownSni,
+}
+
+/// A TLS certificate given on the command line
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone)]
+pub struct TlsCert {
+    /// SNI hostname this certificate is served for (`None` for the default certificate)
+    pub hostname: Option<String>,
+    pub cert: PathBuf,
+    /// Private key belonging to this certificate (`None` means it's taken from --tls-key)
+    pub key: Option<PathBuf>,
+}
+
+/// Format of the request log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Colored, human readable output
+    Pretty,
+    /// One JSON object per request
+    Json,
+    /// One line of key=value pairs per request
+    Logfmt,
+    /// Apache combined log format with the latency in microseconds appended
+    Combined,
+}
+
+/// What generated bodies consist of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BodyPattern {
+    /// Zero bytes
+    Zeros,
+    /// Random bytes, different for every response
+    Random,
+    /// The rendered --body, repeated
+    Text,
+    /// Random bytes, the same for every response given the same --body-seed
+    Seeded,
+}
+
+/// How to compress responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compress {
+    /// Use the best encoding the client accepts, if any
+    Auto,
+    Gzip,
+    Br,
+    Zstd,
+    Deflate,
+}
+
+/// Whether connections have to start with a PROXY protocol header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProxyProtocol {
+    /// Accept connections with and without a header
+    Optional,
+    /// Drop connections without a header
+    Required,
+}
+
+/// Behavior for SNI hostnames we don't have a certificate for
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UnknownSni {
+    /// Serve the default certificate
+    Fallback,
+    /// Abort the TLS handshake
+    Reject,
+}
+
+/// Protocol spoken on a listener
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Http,
+    Https,
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scheme::Http => write!(f, "http"),
+            Scheme::Https => write!(f, "https"),
+        }
+    }
+}
+
+/// An address to listen on as given by --listen
+#[derive(Debug, Clone)]
+pub enum ListenSpec {
+    Tcp {
+        scheme: Scheme,
+        /// IP address or host name (IPv6 addresses without brackets)
+        host: String,
+        port: u16,
+    },
+    /// Plain HTTP on a Unix domain socket
+    Unix(PathBuf),
+}
+
+/// Parse a listen address given in a string format into a `ListenSpec`
+///
+/// Addresses are expected to be in format "scheme://host:port" or "unix:path".
+fn parse_listen(src: &str) -> Result<ListenSpec, String> {
+    let (scheme, address) = if let Some(address) = src.strip_prefix("http://") {
+        (Scheme::Http, address)
+    } else if let Some(address) = src.strip_prefix("https://") {
+        (Scheme::Https, address)
+    } else if let Some(path) = src.strip_prefix("unix:") {
+        if path.is_empty() {
+            return Err("Wrong listen format (see --help for format)".to_string());
+        }
+        return Ok(ListenSpec::Unix(PathBuf::from(path)));
+    } else {
+        return Err("Unknown scheme, expected http://, https:// or unix:".to_string());
+    };
+
+    let Some((host, port)) = address.rsplit_once(':') else {
+        return Err("Wrong listen format (see --help for format)".to_string());
+    };
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    if host.is_empty() {
+        return Err("Wrong listen format (see --help for format)".to_string());
+    }
+    let port = port.parse::<u16>().map_err(|e| e.to_string())?;
+
+    Ok(ListenSpec::Tcp {
+        scheme,
+        host: host.to_string(),
+        port,
+    })
+}
+
+/// Parse file permissions given as an octal string like "660"
+fn parse_mode(src: &str) -> Result<u32, String> {
+    u32::from_str_radix(src, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| "Expected octal permissions like 660".to_string())
+}
+
+/// Parse a size like "512", "64KiB" or "10MB" into bytes
+///
+/// KB, MB and GB are powers of 1000 while KiB, MiB and GiB are powers of 1024.
+pub fn parse_size(src: &str) -> Result<u64, String> {
+    let src = src.trim();
+    let split = src.find(|c: char| !c.is_ascii_digit()).unwrap_or(src.len());
+    let (number, unit) = src.split_at(split);
+    let number = number
+        .parse::<u64>()
+        .map_err(|_| "Expected a size like 512, 64KiB or 10MB".to_string())?;
+    let factor: u64 = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "ki" | "kib" => 1024,
+        "m" | "mb" => 1000 * 1000,
+        "mi" | "mib" => 1024 * 1024,
+        "g" | "gb" => 1000 * 1000 * 1000,
+        "gi" | "gib" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown size unit '{unit}'")),
+    };
+    number
+        .checked_mul(factor)
+        .ok_or_else(|| "Size is too large".to_string())
+}
+
+/// Parse an RFC 3339 timestamp like "2024-01-01T12:00:00Z"
+fn parse_time(src: &str) -> Result<DateTime<FixedOffset>, String> {
+    DateTime::parse_from_rfc3339(src)
+        .map_err(|e| format!("Expected an RFC 3339 time like 2024-01-01T12:00:00Z: {e}"))
+}
+
+/// Parse a clock offset like "90s", "-1d" or "+2h30m"
+fn parse_offset(src: &str) -> Result<TimeDelta, String> {
+    let invalid = || "Expected an offset like 90s, -1d or +2h30m".to_string();
+    let (negative, mut rest) = match src.trim() {
+        rest if rest.starts_with('-') => (true, &rest[1..]),
+        rest => (false, rest.strip_prefix('+').unwrap_or(rest)),
+    };
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut offset = TimeDelta::zero();
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let number = rest[..split].parse::<i64>().map_err(|_| invalid())?;
+        let unit = rest[split..].chars().next().ok_or_else(invalid)?;
+        let part = match unit {
+            'd' => TimeDelta::try_days(number),
+            'h' => TimeDelta::try_hours(number),
+            'm' => TimeDelta::try_minutes(number),
+            's' => TimeDelta::try_seconds(number),
+            _ => return Err(format!("Unknown offset unit '{unit}'")),
+        };
+        offset = part
+            .and_then(|part| offset.checked_add(&part))
+            .ok_or_else(|| "Offset is too large".to_string())?;
+        rest = &rest[split + unit.len_utf8()..];
+    }
+    Ok(if negative { -offset } else { offset })
+}
+
+/// Check that a URL path is absolute
+fn parse_path(src: &str) -> Result<String, String> {
+    if !src.starts_with('/') {
+        return Err("Expected a path starting with /".to_string());
+    }
+    Ok(src.to_string())
 }
 
 /// Checks wether an interface is valid, i.e. it can be parsed into an IP address
@@ -80,21 +506,64 @@ fn parse_interface(src: &str) -> Result<IpAddr, std::net::AddrParseError> {
     src.parse::<IpAddr>()
 }
 
+/// Parse a TLS certificate given in a string format into a `TlsCert`
+///
+/// Certificates are expected to be in format "path" or "hostname=cert_path,key_path".
+#[cfg(feature = "tls")]
+fn parse_tls_cert(src: &str) -> Result<TlsCert, String> {
+    let Some((hostname, paths)) = src.split_once('=') else {
+        return Ok(TlsCert {
+            hostname: None,
+            cert: PathBuf::from(src),
+            key: None,
+        });
+    };
+
+    let Some((cert, key)) = paths.split_once(',') else {
+        return Err("Wrong certificate format (see --help for format)".to_string());
+    };
+    if hostname.is_empty() || cert.is_empty() || key.is_empty() {
+        return Err("Wrong certificate format (see --help for format)".to_string());
+    }
+
+    Ok(TlsCert {
+        hostname: Some(hostname.to_lowercase()),
+        cert: PathBuf::from(cert),
+        key: Some(PathBuf::from(key)),
+    })
+}
+
+/// Check that the status code is either valid or a template rendering one per request
+fn parse_code(src: &str) -> Result<String, String> {
+    if has_template_syntax(src) {
+        return Ok(src.to_string());
+    }
+    parse_status(src).map(|_| src.to_string())
+}
+
+/// Parse a status code, which is how the rendered --code template gets validated as well
+pub fn parse_status(src: &str) -> Result<StatusCode, String> {
+    src.trim()
+        .parse::<u16>()
+        .ok()
+        .filter(|code| (100..1000).contains(code))
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .ok_or_else(|| format!("`{src}` is not a valid status code (100-999)"))
+}
+
 /// Parse a header given in a string format into a `HeaderMap`
 ///
-/// Headers are expected to be in format "key:value".
+/// Headers are expected to be in format "key:value". The value may contain further colons and
+/// surrounding whitespace is dropped.
 fn parse_header(header: &str) -> Result<HeaderMap, String> {
-    let header: Vec<&str> = header.split(':').collect();
-    if header.len() != 2 {
+    let Some((header_name, header_value)) = header.split_once(':') else {
         return Err("Wrong header format (see --help for format)".to_string());
-    }
-
-    let (header_name, header_value) = (header[0], header[1]);
+    };
 
-    let hn = HeaderName::from_lowercase(header_name.to_lowercase().as_bytes())
+    let hn = HeaderName::from_lowercase(header_name.trim().to_lowercase().as_bytes())
         .map_err(|e| e.to_string())?;
 
-    let hv = HeaderValue::from_str(header_value).map_err(|e| e.to_string())?;
+    let hv = HeaderValue::from_str(header_value.trim()).map_err(|e| e.to_string())?;
 
     let mut map = HeaderMap::new();
     map.insert(hn, hv);
@@ -0,0 +1,262 @@
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use axum::{
+    body::Body,
+    http::{HeaderMap, StatusCode},
+};
+use bytes::{Bytes, BytesMut};
+use http_body::{Frame, SizeHint};
+use http_body_util::BodyExt;
+use hyper::header::CONTENT_LENGTH;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+
+use crate::args::BodyPattern;
+
+/// Size of the chunks generated bodies are sent in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A body as far as we kept it around for logging
+#[derive(Debug, Clone)]
+pub struct CapturedBody {
+    /// The whole body or, if it was larger than the log limit, its beginning
+    pub bytes: Bytes,
+    /// Size of the whole body
+    pub size: u64,
+    /// Hex encoded SHA-256 of the whole body if we didn't keep all of it
+    pub sha256: Option<String>,
+}
+
+/// Keeps the first `limit` bytes of a body while counting and hashing all of it
+pub struct Capture {
+    limit: usize,
+    prefix: BytesMut,
+    size: u64,
+    hasher: Sha256,
+}
+
+impl Capture {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            prefix: BytesMut::new(),
+            size: 0,
+            hasher: Sha256::new(),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        let keep = chunk
+            .len()
+            .min(self.limit.saturating_sub(self.prefix.len()));
+        self.prefix.extend_from_slice(&chunk[..keep]);
+        self.size += chunk.len() as u64;
+        self.hasher.update(chunk);
+    }
+
+    pub fn finish(self) -> CapturedBody {
+        let truncated = self.size > self.prefix.len() as u64;
+        let sha256 = truncated.then(|| {
+            self.hasher
+                .finalize()
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect()
+        });
+        CapturedBody {
+            bytes: self.prefix.freeze(),
+            size: self.size,
+            sha256,
+        }
+    }
+}
+
+/// Read a request body chunk by chunk, keeping at most its first `keep` bytes
+///
+/// The rest is read and thrown away so that we can act as a sink for uploads of any size. Stops
+/// reading as soon as the body turns out to be larger than `max_size` and returns the 413 to send
+/// instead.
+pub async fn read_request_body(
+    mut body: Body,
+    headers: &HeaderMap,
+    max_size: Option<u64>,
+    keep: usize,
+) -> Result<Bytes, (StatusCode, String)> {
+    let too_large = |max_size| {
+        (
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Request body is larger than {max_size} bytes"),
+        )
+    };
+
+    let content_length = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if let (Some(max_size), Some(content_length)) = (max_size, content_length) {
+        if content_length > max_size {
+            return Err(too_large(max_size));
+        }
+    }
+
+    let mut bytes = BytesMut::new();
+    let mut size = 0;
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|err| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("failed to read request body: {err}"),
+            )
+        })?;
+        if let Ok(chunk) = frame.into_data() {
+            size += chunk.len() as u64;
+            if let Some(max_size) = max_size.filter(|&max_size| size > max_size) {
+                return Err(too_large(max_size));
+            }
+            let take = chunk.len().min(keep.saturating_sub(bytes.len()));
+            bytes.extend_from_slice(&chunk[..take]);
+        }
+    }
+
+    Ok(bytes.freeze())
+}
+
+/// Request or response body passing everything through while capturing it for the log
+///
+/// `on_complete` gets called once the body has been read or sent or, if it's dropped early,
+/// with whatever went through until then.
+pub struct CapturingBody {
+    inner: Body,
+    capture: Option<Capture>,
+    on_complete: Option<Box<dyn FnOnce(CapturedBody) + Send>>,
+}
+
+impl CapturingBody {
+    pub fn new(
+        inner: Body,
+        log_limit: usize,
+        on_complete: impl FnOnce(CapturedBody) + Send + 'static,
+    ) -> Self {
+        Self {
+            inner,
+            capture: Some(Capture::new(log_limit)),
+            on_complete: Some(Box::new(on_complete)),
+        }
+    }
+
+    fn complete(&mut self) {
+        if let (Some(capture), Some(on_complete)) = (self.capture.take(), self.on_complete.take()) {
+            on_complete(capture.finish());
+        }
+    }
+}
+
+impl http_body::Body for CapturingBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = ready!(Pin::new(&mut self.inner).poll_frame(cx));
+        if let (Some(Ok(frame)), Some(capture)) = (&frame, &mut self.capture) {
+            if let Some(data) = frame.data_ref() {
+                capture.update(data);
+            }
+        }
+        // The server might never poll again once the body says it's done.
+        if frame.is_none() || self.inner.is_end_stream() {
+            self.complete();
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for CapturingBody {
+    fn drop(&mut self) {
+        self.complete();
+    }
+}
+
+/// Where a generated body gets its bytes from
+enum Source {
+    /// A chunk repeating the pattern which we hand out slices of
+    Repeat(Bytes),
+    Random(Box<StdRng>),
+}
+
+/// Body of a given size generated on the fly rather than held in memory
+pub struct GeneratedBody {
+    source: Source,
+    remaining: u64,
+    /// Whether to announce the size upfront (Content-Length) or to send it chunked
+    exact_size: bool,
+}
+
+impl GeneratedBody {
+    /// `text` is what gets repeated for `BodyPattern::Text` and `seed` seeds
+    /// `BodyPattern::Seeded`.
+    pub fn new(pattern: BodyPattern, size: u64, text: &[u8], seed: u64, exact_size: bool) -> Self {
+        let source = match pattern {
+            BodyPattern::Text if !text.is_empty() => {
+                Source::Repeat(text.repeat(CHUNK_SIZE.div_ceil(text.len())).into())
+            }
+            BodyPattern::Zeros | BodyPattern::Text => Source::Repeat(vec![0; CHUNK_SIZE].into()),
+            BodyPattern::Random => Source::Random(Box::new(rand::make_rng())),
+            BodyPattern::Seeded => Source::Random(Box::new(StdRng::seed_from_u64(seed))),
+        };
+        Self {
+            source,
+            remaining: size,
+            exact_size,
+        }
+    }
+}
+
+impl http_body::Body for GeneratedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        let remaining = usize::try_from(self.remaining).unwrap_or(usize::MAX);
+        let chunk = match &mut self.source {
+            // The chunk holds a whole number of repetitions so consecutive slices line up.
+            Source::Repeat(chunk) => chunk.slice(..remaining.min(chunk.len())),
+            Source::Random(rng) => {
+                let mut chunk = vec![0; remaining.min(CHUNK_SIZE)];
+                rng.fill_bytes(&mut chunk);
+                chunk.into()
+            }
+        };
+        self.remaining -= chunk.len() as u64;
+        Poll::Ready(Some(Ok(Frame::data(chunk))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.exact_size {
+            SizeHint::with_exact(self.remaining)
+        } else {
+            SizeHint::default()
+        }
+    }
+}
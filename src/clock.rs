@@ -0,0 +1,28 @@
+use chrono::{DateTime, FixedOffset, Local, TimeDelta};
+
+use crate::args::Args;
+
+/// Where we get the current time from for templates, the date header and the log
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Clock {
+    /// Time it always is, set via --fixed-time
+    fixed: Option<DateTime<FixedOffset>>,
+    /// How far to shift the system clock, set via --clock-offset
+    offset: TimeDelta,
+}
+
+impl Clock {
+    pub fn new(args: &Args) -> Self {
+        Self {
+            fixed: args.fixed_time,
+            offset: args.clock_offset.unwrap_or_default(),
+        }
+    }
+
+    pub fn now(&self) -> DateTime<Local> {
+        match self.fixed {
+            Some(fixed) => fixed.with_timezone(&Local),
+            None => Local::now() + self.offset,
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use std::io::{self, Read, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use flate2::{
+    read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder},
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use http_body::{Body as _, Frame};
+use hyper::{
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY},
+    HeaderMap,
+};
+
+use crate::args::Compress;
+use crate::body::CapturedBody;
+use crate::AppState;
+
+/// Encodings we can compress with, in the order we prefer them if the client likes them equally
+const SUPPORTED_ENCODINGS: [&str; 4] = ["br", "zstd", "gzip", "deflate"];
+
+/// Refuse to inflate bodies beyond this so a tiny zip bomb can't eat all our memory
+const MAX_DECODED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A body as we log it, decompressed according to its `Content-Encoding` if possible
+#[derive(Debug, Clone)]
+pub struct DisplayBody {
+    /// The decompressed body or, if that failed or we only kept its beginning, the body as it
+    /// was sent
+    pub bytes: Bytes,
+    /// Size of the body as it was sent
+    pub wire_size: u64,
+    /// Hex encoded SHA-256 of the body as it was sent if we only kept its beginning
+    pub sha256: Option<String>,
+    /// The `Content-Encoding` of the body, if any
+    pub encoding: Option<String>,
+    /// Why we couldn't decompress the body
+    pub error: Option<String>,
+}
+
+impl DisplayBody {
+    /// Describe what we did to the body, e.g. "gzip, 31 → 120 bytes"
+    pub fn note(&self) -> Option<String> {
+        let mut notes = vec![];
+        if let Some(sha256) = &self.sha256 {
+            notes.push(format!(
+                "first {} of {} bytes, sha256 {sha256}",
+                self.bytes.len(),
+                self.wire_size
+            ));
+        }
+        if let Some(encoding) = &self.encoding {
+            notes.push(match &self.error {
+                Some(error) => format!("{encoding}, failed to decompress: {error}"),
+                None if self.sha256.is_some() => format!("{encoding}, not decompressed"),
+                None => format!(
+                    "{encoding}, {} → {} bytes",
+                    self.wire_size,
+                    self.bytes.len()
+                ),
+            });
+        }
+        (!notes.is_empty()).then(|| notes.join(", "))
+    }
+}
+
+/// Read `reader` to the end unless it inflates beyond `MAX_DECODED_SIZE`
+fn read_limited(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut decoded = vec![];
+    reader
+        .take(MAX_DECODED_SIZE + 1)
+        .read_to_end(&mut decoded)?;
+    if decoded.len() as u64 > MAX_DECODED_SIZE {
+        return Err(io::Error::other(format!(
+            "more than {} MiB after decompression",
+            MAX_DECODED_SIZE / 1024 / 1024
+        )));
+    }
+    Ok(decoded)
+}
+
+/// Undo a single content coding
+fn decode_one(bytes: &[u8], encoding: &str) -> io::Result<Vec<u8>> {
+    match encoding {
+        "gzip" | "x-gzip" => read_limited(MultiGzDecoder::new(bytes)),
+        // "deflate" is supposed to be zlib-wrapped but plenty of clients send raw deflate data.
+        "deflate" => read_limited(ZlibDecoder::new(bytes))
+            .or_else(|_| read_limited(DeflateDecoder::new(bytes))),
+        "br" => read_limited(brotli::Decompressor::new(bytes, 4096)),
+        "zstd" => read_limited(zstd::stream::Decoder::new(bytes)?),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "unsupported encoding",
+        )),
+    }
+}
+
+/// Decompress `body` according to the `Content-Encoding` in `headers` for logging
+///
+/// Bodies we only kept the beginning of are left alone as they can't be decompressed anyway.
+pub fn decode(body: CapturedBody, headers: &HeaderMap) -> DisplayBody {
+    let encodings = headers
+        .get_all(CONTENT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|encoding| encoding.trim().to_lowercase())
+        .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+        .collect::<Vec<_>>();
+
+    let mut display_body = DisplayBody {
+        bytes: body.bytes,
+        wire_size: body.size,
+        sha256: body.sha256,
+        encoding: None,
+        error: None,
+    };
+    if encodings.is_empty() || display_body.bytes.is_empty() {
+        return display_body;
+    }
+    display_body.encoding = Some(encodings.join(", "));
+    if display_body.sha256.is_some() {
+        return display_body;
+    }
+
+    // Codings are listed in the order they were applied so we undo them back to front.
+    let mut decoded = display_body.bytes.to_vec();
+    for encoding in encodings.iter().rev() {
+        match decode_one(&decoded, encoding) {
+            Ok(bytes) => decoded = bytes,
+            Err(e) => {
+                display_body.error = Some(e.to_string());
+                return display_body;
+            }
+        }
+    }
+    display_body.bytes = decoded.into();
+    display_body
+}
+
+/// Compressor for a single content coding, writing into a buffer we take the output from
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(ZlibEncoder<Vec<u8>>),
+    Br(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: &str) -> io::Result<Self> {
+        match encoding {
+            "gzip" => Ok(Self::Gzip(GzEncoder::new(vec![], Compression::default()))),
+            "deflate" => Ok(Self::Deflate(ZlibEncoder::new(
+                vec![],
+                Compression::default(),
+            ))),
+            "br" => Ok(Self::Br(Box::new(brotli::CompressorWriter::new(
+                vec![],
+                4096,
+                5,
+                22,
+            )))),
+            "zstd" => Ok(Self::Zstd(zstd::stream::write::Encoder::new(vec![], 0)?)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported encoding",
+            )),
+        }
+    }
+
+    /// Compress `chunk`, returning as much of the compressed output as is ready so far
+    fn compress(&mut self, chunk: &[u8]) -> io::Result<Bytes> {
+        let output = match self {
+            Self::Gzip(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+            Self::Deflate(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+            Self::Br(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+            Self::Zstd(encoder) => {
+                encoder.write_all(chunk)?;
+                encoder.get_mut()
+            }
+        };
+        Ok(std::mem::take(output).into())
+    }
+
+    /// The rest of the compressed output
+    fn finish(self) -> io::Result<Bytes> {
+        let output = match self {
+            Self::Gzip(encoder) => encoder.finish()?,
+            Self::Deflate(encoder) => encoder.finish()?,
+            Self::Br(encoder) => encoder.into_inner(),
+            Self::Zstd(encoder) => encoder.finish()?,
+        };
+        Ok(output.into())
+    }
+}
+
+/// Response body compressed chunk by chunk as it is sent so it never has to be in memory as a
+/// whole
+struct CompressedBody {
+    inner: Body,
+    /// Taken once the inner body is done and the compressed output is complete
+    encoder: Option<Encoder>,
+}
+
+impl http_body::Body for CompressedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = &mut *self;
+        loop {
+            let Some(encoder) = &mut this.encoder else {
+                return Poll::Ready(None);
+            };
+            match ready!(Pin::new(&mut this.inner).poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        let compressed = encoder.compress(&data).map_err(axum::Error::new)?;
+                        // Compressors hold on to their input for a while so there might be
+                        // nothing to send yet.
+                        if !compressed.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(compressed))));
+                        }
+                    }
+                    Err(frame) => return Poll::Ready(Some(Ok(frame))),
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => {
+                    let encoder = this.encoder.take().unwrap();
+                    let rest = encoder.finish().map_err(axum::Error::new)?;
+                    return Poll::Ready(Some(Ok(Frame::data(rest))));
+                }
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.encoder.is_none()
+    }
+}
+
+/// The quality value (`q=`) the client assigned to `encoding` in its `Accept-Encoding` header
+fn quality(accept_encoding: &str, encoding: &str) -> f32 {
+    let mut wildcard = 0.0;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';').map(str::trim);
+        let name = params.next().unwrap_or("").to_lowercase();
+        let q = params
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name == encoding || (encoding == "gzip" && name == "x-gzip") {
+            return q;
+        } else if name == "*" {
+            wildcard = q;
+        }
+    }
+    wildcard
+}
+
+/// Pick the encoding the client prefers out of the ones we support
+fn negotiate(accept_encoding: &str) -> Option<&'static str> {
+    let mut best = None;
+    for encoding in SUPPORTED_ENCODINGS {
+        let q = quality(accept_encoding, encoding);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Compress responses as requested by `--compress`
+pub async fn compress_response(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(compress) = state.args.compress else {
+        return next.run(req).await;
+    };
+    let accept_encoding = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+
+    let resp = next.run(req).await;
+    let (mut parts, body) = resp.into_parts();
+    if compress == Compress::Auto {
+        parts
+            .headers
+            .append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    let encoding = match compress {
+        Compress::Auto => negotiate(&accept_encoding),
+        Compress::Gzip => Some("gzip"),
+        Compress::Br => Some("br"),
+        Compress::Zstd => Some("zstd"),
+        Compress::Deflate => Some("deflate"),
+    };
+    let Some(encoding) = encoding.filter(|_| !parts.headers.contains_key(CONTENT_ENCODING)) else {
+        return Response::from_parts(parts, body);
+    };
+
+    if body.size_hint().exact() == Some(0) {
+        return Response::from_parts(parts, body);
+    }
+    match Encoder::new(encoding) {
+        Ok(encoder) => {
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+            parts.headers.remove(CONTENT_LENGTH);
+            let body = CompressedBody {
+                inner: body,
+                encoder: Some(encoder),
+            };
+            Response::from_parts(parts, Body::new(body))
+        }
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to compress response body: {err}"),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,323 @@
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use fake::faker::{
+    address::en::{BuildingNumber, CityName, CountryName, StateAbbr, StreetName, ZipCode},
+    company::en::CompanyName,
+    internet::en::{DomainSuffix, FreeEmail, IPv4, IPv6, Username},
+    lorem::en::Word,
+    name::en::{FirstName, LastName, Name},
+    phone_number::en::PhoneNumber,
+};
+use fake::rand::{rngs::StdRng, seq::IndexedRandom, Rng, SeedableRng};
+use fake::Fake;
+use regex_syntax::hir::{Class, ClassUnicode, ClassUnicodeRange, Hir, HirKind};
+use tera::{Kwargs, State, Tera, TeraResult, Value};
+
+thread_local! {
+    /// Random number generator of the request whose templates are being rendered on this thread
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_os_rng());
+}
+
+/// Account number lengths of the countries we generate IBANs for, all of which are purely numeric
+const IBAN_COUNTRIES: [(&str, usize); 6] = [
+    ("AT", 16),
+    ("BE", 12),
+    ("CH", 17),
+    ("DE", 18),
+    ("ES", 20),
+    ("PL", 24),
+];
+
+/// How many repetitions beyond the minimum unbounded repetitions like `*` and `+` produce at most
+const MAX_EXTRA_REPETITIONS: u32 = 8;
+
+/// Hands out a random number generator for every request
+///
+/// With a seed, the generator only depends on the seed and the number of the request so that
+/// the nth request gets the same data on every run, no matter what happened before.
+pub struct RequestRngs {
+    seed: Option<u64>,
+    requests: AtomicU64,
+}
+
+impl RequestRngs {
+    pub fn new(seed: Option<u64>) -> Self {
+        Self {
+            seed,
+            requests: AtomicU64::new(0),
+        }
+    }
+
+    /// Run `render` with the generator for the next request in place for the template functions
+    pub fn with_next<T>(&self, render: impl FnOnce() -> T) -> T {
+        let index = self.requests.fetch_add(1, Ordering::Relaxed);
+        let rng = match self.seed {
+            Some(seed) => {
+                let mut rng_seed = [0; 32];
+                rng_seed[..8].copy_from_slice(&seed.to_le_bytes());
+                rng_seed[8..16].copy_from_slice(&index.to_le_bytes());
+                StdRng::from_seed(rng_seed)
+            }
+            None => StdRng::from_rng(&mut fake::rand::rng()),
+        };
+        RNG.set(rng);
+        render()
+    }
+}
+
+/// Run `f` with the random number generator of the request being rendered
+pub fn with_rng<T>(f: impl FnOnce(&mut StdRng) -> T) -> T {
+    RNG.with_borrow_mut(f)
+}
+
+/// Register a function generating data from its arguments and the request's generator
+fn register<F, V>(tera: &mut Tera, name: &'static str, generate: F)
+where
+    F: Fn(&Kwargs, &mut StdRng) -> TeraResult<V> + Send + Sync + 'static,
+    V: Into<Value>,
+{
+    tera.register_function(name, move |kwargs: Kwargs, _state: &State<'_>| {
+        with_rng(|rng| generate(&kwargs, rng)).map(Into::into)
+    });
+}
+
+/// Register all fake data functions
+pub fn register_functions(tera: &mut Tera) {
+    register(tera, "first_name", |_, rng| {
+        Ok(FirstName().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "last_name", |_, rng| {
+        Ok(LastName().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "name", |_, rng| {
+        Ok(Name().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "username", |_, rng| {
+        Ok(Username().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "email", |_, rng| {
+        Ok(FreeEmail().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "phone", |_, rng| {
+        Ok(PhoneNumber().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "address", |_, rng| {
+        Ok(format!(
+            "{} {}, {}, {} {}",
+            BuildingNumber().fake_with_rng::<String, _>(rng),
+            StreetName().fake_with_rng::<String, _>(rng),
+            CityName().fake_with_rng::<String, _>(rng),
+            StateAbbr().fake_with_rng::<String, _>(rng),
+            ZipCode().fake_with_rng::<String, _>(rng),
+        ))
+    });
+    register(tera, "city", |_, rng| {
+        Ok(CityName().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "country", |_, rng| {
+        Ok(CountryName().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "company", |_, rng| {
+        Ok(CompanyName().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "ipv4", |_, rng| {
+        Ok(IPv4().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "ipv6", |_, rng| {
+        Ok(IPv6().fake_with_rng::<String, _>(rng))
+    });
+    register(tera, "url", |_, rng| {
+        Ok(format!(
+            "https://{}.{}/{}",
+            Word().fake_with_rng::<String, _>(rng).to_lowercase(),
+            DomainSuffix().fake_with_rng::<String, _>(rng),
+            Word().fake_with_rng::<String, _>(rng).to_lowercase(),
+        ))
+    });
+    register(tera, "iban", |kwargs, rng| {
+        iban(kwargs.get("country")?.unwrap_or("DE"), rng)
+    });
+    register(tera, "date_between", date_between);
+    register(tera, "int", |kwargs, rng| {
+        let min = kwargs.get::<i64>("min")?.unwrap_or(0);
+        let max = kwargs.get::<i64>("max")?.unwrap_or(100);
+        if min > max {
+            return Err(tera::Error::message("int() needs min <= max"));
+        }
+        Ok(rng.random_range(min..=max))
+    });
+    register(tera, "float", |kwargs, rng| {
+        let min = kwargs.get::<f64>("min")?.unwrap_or(0.0);
+        let max = kwargs.get::<f64>("max")?.unwrap_or(1.0);
+        if min > max {
+            return Err(tera::Error::message("float() needs min <= max"));
+        }
+        Ok(min + rng.random::<f64>() * (max - min))
+    });
+    register(tera, "bool", |kwargs, rng| {
+        let probability = kwargs.get::<f64>("probability")?.unwrap_or(0.5);
+        if !(0.0..=1.0).contains(&probability) {
+            return Err(tera::Error::message(
+                "bool() needs a probability between 0 and 1",
+            ));
+        }
+        Ok(rng.random_bool(probability))
+    });
+    register(tera, "pick", |kwargs, rng| {
+        kwargs
+            .must_get::<&[Value]>("list")?
+            .choose(rng)
+            .cloned()
+            .ok_or_else(|| tera::Error::message("pick() needs a non-empty list"))
+    });
+    register(tera, "regex", |kwargs, rng| {
+        let pattern = kwargs.must_get::<&str>("pattern")?;
+        let hir = regex_syntax::parse(pattern).map_err(|err| {
+            tera::Error::message(format!("regex() got an invalid pattern: {err}"))
+        })?;
+        let mut generated = String::new();
+        generate_match(&hir, rng, &mut generated);
+        Ok(generated)
+    });
+}
+
+/// An IBAN with valid check digits for `country`
+fn iban(country: &str, rng: &mut StdRng) -> TeraResult<String> {
+    let country = country.to_uppercase();
+    let Some(&(_, length)) = IBAN_COUNTRIES.iter().find(|(code, _)| *code == country) else {
+        let supported = IBAN_COUNTRIES.map(|(code, _)| code).join(", ");
+        return Err(tera::Error::message(format!(
+            "iban() supports the countries {supported}"
+        )));
+    };
+    let account = (0..length)
+        .map(|_| char::from(b'0' + rng.random_range(0..10)))
+        .collect::<String>();
+
+    // The check digits make the account followed by the country (as numbers) and the check
+    // digits themselves come out as 1 mod 97.
+    let remainder = format!("{account}{country}00")
+        .chars()
+        .filter_map(|c| c.to_digit(36))
+        .fold(0, |remainder, digit| {
+            let shift = if digit < 10 { 10 } else { 100 };
+            (remainder * shift + digit) % 97
+        });
+    Ok(format!("{country}{:02}{account}", 98 - remainder))
+}
+
+/// Parse an RFC 3339 timestamp or a plain date, telling which one it was
+fn parse_date(value: &str) -> Option<(DateTime<Utc>, bool)> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Some((date.and_hms_opt(0, 0, 0)?.and_utc(), true));
+    }
+    let time = DateTime::parse_from_rfc3339(value).ok()?;
+    Some((time.to_utc(), false))
+}
+
+/// A date between `start` and `end` (both inclusive), either plain dates or RFC 3339 timestamps
+///
+/// Plain dates produce a plain date, timestamps a timestamp unless `format` is given.
+fn date_between(kwargs: &Kwargs, rng: &mut StdRng) -> TeraResult<String> {
+    let parse = |name| {
+        let value = kwargs.must_get::<&str>(name)?;
+        parse_date(value).ok_or_else(|| {
+            tera::Error::message(format!(
+                "date_between() needs `{name}` as YYYY-MM-DD or RFC 3339, got `{value}`"
+            ))
+        })
+    };
+    let (start, start_is_date) = parse("start")?;
+    let (end, end_is_date) = parse("end")?;
+    if start > end {
+        return Err(tera::Error::message("date_between() needs start <= end"));
+    }
+
+    let timestamp = rng.random_range(start.timestamp()..=end.timestamp());
+    let date = DateTime::from_timestamp(timestamp, 0).unwrap_or(start);
+    let format = match kwargs.get::<&str>("format")? {
+        Some(format) => format,
+        None if start_is_date && end_is_date => "%Y-%m-%d",
+        None => return Ok(date.to_rfc3339()),
+    };
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format(format))
+        .map_err(|_| tera::Error::message(format!("Invalid date format `{format}`")))?;
+    Ok(formatted)
+}
+
+/// Append a random string matching `hir` to `out`
+///
+/// Where a character class allows for it, we stick to printable ASCII so that e.g. `\d` or `.`
+/// don't produce digits and letters from all over Unicode.
+fn generate_match(hir: &Hir, rng: &mut StdRng, out: &mut String) {
+    match hir.kind() {
+        HirKind::Empty | HirKind::Look(_) => {}
+        HirKind::Literal(literal) => out.push_str(&String::from_utf8_lossy(&literal.0)),
+        HirKind::Class(Class::Unicode(class)) => {
+            let mut printable = class.clone();
+            printable.intersect(&ClassUnicode::new([ClassUnicodeRange::new(' ', '~')]));
+            let class = if printable.ranges().is_empty() {
+                class
+            } else {
+                &printable
+            };
+            let ranges = class
+                .ranges()
+                .iter()
+                .map(|range| (u32::from(range.start()), u32::from(range.end())));
+            out.extend(pick_from_ranges(ranges, rng).and_then(char::from_u32));
+        }
+        HirKind::Class(Class::Bytes(class)) => {
+            let ranges = class
+                .ranges()
+                .iter()
+                .map(|range| (u32::from(range.start()), u32::from(range.end())));
+            out.extend(pick_from_ranges(ranges, rng).and_then(char::from_u32));
+        }
+        HirKind::Repetition(repetition) => {
+            let max = repetition
+                .max
+                .unwrap_or(repetition.min.saturating_add(MAX_EXTRA_REPETITIONS));
+            for _ in 0..rng.random_range(repetition.min..=max) {
+                generate_match(&repetition.sub, rng, out);
+            }
+        }
+        HirKind::Capture(capture) => generate_match(&capture.sub, rng, out),
+        HirKind::Concat(hirs) => {
+            for hir in hirs {
+                generate_match(hir, rng, out);
+            }
+        }
+        HirKind::Alternation(hirs) => {
+            if let Some(hir) = hirs.choose(rng) {
+                generate_match(hir, rng, out);
+            }
+        }
+    }
+}
+
+/// Pick a value uniformly from a set of inclusive ranges
+fn pick_from_ranges(
+    ranges: impl Iterator<Item = (u32, u32)> + Clone,
+    rng: &mut StdRng,
+) -> Option<u32> {
+    let total = ranges
+        .clone()
+        .map(|(start, end)| end - start + 1)
+        .sum::<u32>();
+    if total == 0 {
+        return None;
+    }
+    let mut index = rng.random_range(0..total);
+    for (start, end) in ranges {
+        if index <= end - start {
+            return Some(start + index);
+        }
+        index -= end - start + 1;
+    }
+    None
+}
@@ -0,0 +1,130 @@
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use hmac::{Hmac, KeyInit, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use tera::{Kwargs, State, Tera, TeraResult};
+
+/// Everything but the unreserved characters of RFC 3986 gets percent-encoded
+const URL_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Register the encoding, hashing and signing filters
+pub fn register_filters(tera: &mut Tera) {
+    tera.register_filter("base64_encode", base64_encode);
+    tera.register_filter("base64_decode", base64_decode);
+    tera.register_filter("base64url_encode", base64url_encode);
+    tera.register_filter("base64url_decode", base64url_decode);
+    tera.register_filter("hex_encode", hex_encode);
+    tera.register_filter("hex_decode", hex_decode);
+    tera.register_filter("urlencode", urlencode);
+    tera.register_filter("urldecode", urldecode);
+    tera.register_filter("json_escape", json_escape);
+    tera.register_filter("sha1", hash::<Sha1>);
+    tera.register_filter("sha256", hash::<Sha256>);
+    tera.register_filter("sha512", hash::<Sha512>);
+    tera.register_filter("hmac_sha1", hmac::<Hmac<Sha1>>);
+    tera.register_filter("hmac_sha256", hmac::<Hmac<Sha256>>);
+    tera.register_filter("hmac_sha512", hmac::<Hmac<Sha512>>);
+}
+
+/// Decoded bytes as a string, which is all templates can deal with
+fn decoded_text(bytes: Vec<u8>, filter: &str) -> TeraResult<String> {
+    String::from_utf8(bytes)
+        .map_err(|_| tera::Error::message(format!("{filter} decoded to invalid UTF-8")))
+}
+
+fn base64_encode(value: &str, _: Kwargs, _: &State<'_>) -> String {
+    STANDARD.encode(value)
+}
+
+fn base64_decode(value: &str, _: Kwargs, _: &State<'_>) -> TeraResult<String> {
+    let bytes = STANDARD
+        .decode(value.trim())
+        .map_err(|err| tera::Error::message(format!("base64_decode got invalid base64: {err}")))?;
+    decoded_text(bytes, "base64_decode")
+}
+
+/// URL-safe base64 without padding as used by e.g. JWTs
+fn base64url_encode(value: &str, _: Kwargs, _: &State<'_>) -> String {
+    URL_SAFE_NO_PAD.encode(value)
+}
+
+fn base64url_decode(value: &str, _: Kwargs, _: &State<'_>) -> TeraResult<String> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(value.trim().trim_end_matches('='))
+        .map_err(|err| {
+            tera::Error::message(format!("base64url_decode got invalid base64url: {err}"))
+        })?;
+    decoded_text(bytes, "base64url_decode")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn hex_encode(value: &str, _: Kwargs, _: &State<'_>) -> String {
+    hex(value.as_bytes())
+}
+
+fn hex_decode(value: &str, _: Kwargs, _: &State<'_>) -> TeraResult<String> {
+    let invalid = || tera::Error::message("hex_decode got invalid hex");
+    let value = value.trim();
+    if !value.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    let bytes = (0..value.len())
+        .step_by(2)
+        .map(|i| {
+            value
+                .get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(invalid)?;
+    decoded_text(bytes, "hex_decode")
+}
+
+fn urlencode(value: &str, _: Kwargs, _: &State<'_>) -> String {
+    utf8_percent_encode(value, URL_ENCODE_SET).to_string()
+}
+
+fn urldecode(value: &str, _: Kwargs, _: &State<'_>) -> TeraResult<String> {
+    decoded_text(percent_decode_str(value).collect(), "urldecode")
+}
+
+/// Escape a string for use inside a JSON string literal
+fn json_escape(value: &str, _: Kwargs, _: &State<'_>) -> String {
+    let quoted = serde_json::Value::from(value).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+/// Hashes and MACs are hex encoded unless asked for `encoding="base64"`
+fn digest_output(bytes: &[u8], kwargs: &Kwargs) -> TeraResult<String> {
+    match kwargs.get::<&str>("encoding")?.unwrap_or("hex") {
+        "hex" => Ok(hex(bytes)),
+        "base64" => Ok(STANDARD.encode(bytes)),
+        "base64url" => Ok(URL_SAFE_NO_PAD.encode(bytes)),
+        encoding => Err(tera::Error::message(format!(
+            "Unknown encoding `{encoding}`, expected hex, base64 or base64url"
+        ))),
+    }
+}
+
+fn hash<D: Digest>(value: &str, kwargs: Kwargs, _: &State<'_>) -> TeraResult<String> {
+    digest_output(&D::digest(value), &kwargs)
+}
+
+fn hmac<M: Mac + KeyInit>(value: &str, kwargs: Kwargs, _: &State<'_>) -> TeraResult<String> {
+    let key = kwargs.must_get::<&str>("key")?;
+    let mut mac = <M as KeyInit>::new_from_slice(key.as_bytes())
+        .map_err(|err| tera::Error::message(format!("Invalid HMAC key: {err}")))?;
+    mac.update(value.as_bytes());
+    digest_output(&mac.finalize().into_bytes(), &kwargs)
+}
@@ -0,0 +1,121 @@
+use std::io;
+use std::net::UdpSocket;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use anyhow::Result;
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderValue, Request, Response},
+    Router,
+};
+use bytes::Buf;
+use h3::server::{RequestResolver, RequestStream};
+use http_body::Frame;
+use http_body_util::BodyExt;
+use tower::ServiceExt;
+
+use crate::peer::Peer;
+
+/// `Alt-Svc` header value advertising HTTP/3 on TCP listeners
+#[derive(Debug, Clone)]
+pub struct AltSvc(pub HeaderValue);
+
+impl AltSvc {
+    /// Advertise HTTP/3 on `port` for a day
+    pub fn for_port(port: u16) -> Self {
+        Self(HeaderValue::from_str(&format!("h3=\":{port}\"; ma=86400")).unwrap())
+    }
+}
+
+/// Serve `app` via HTTP/3 on `socket`
+pub async fn serve(socket: UdpSocket, config: quinn::ServerConfig, app: Router) -> io::Result<()> {
+    let endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        Some(config),
+        socket,
+        Arc::new(quinn::TokioRuntime),
+    )?;
+
+    while let Some(incoming) = endpoint.accept().await {
+        let app = app.clone();
+        tokio::spawn(async move {
+            // Errors here only concern a single misbehaving client so we don't bring the
+            // server down over them.
+            let _ = handle_connection(incoming, app).await;
+        });
+    }
+    Ok(())
+}
+
+/// Accept all requests made on a single QUIC connection
+async fn handle_connection(incoming: quinn::Incoming, app: Router) -> Result<()> {
+    let connection = incoming.await?;
+    let peer = Peer::Ip(connection.remote_address());
+    let mut connection = h3::server::Connection::new(h3_quinn::Connection::new(connection)).await?;
+
+    while let Some(resolver) = connection.accept().await? {
+        let app = app.clone();
+        let peer = peer.clone();
+        tokio::spawn(async move {
+            let _ = handle_request(resolver, app, peer).await;
+        });
+    }
+    Ok(())
+}
+
+/// Pass a single HTTP/3 request to `app` and send back its response
+async fn handle_request(
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+    app: Router,
+    peer: Peer,
+) -> Result<()> {
+    let (req, stream) = resolver.resolve_request().await?;
+    let (mut send, recv) = stream.split();
+
+    // The request body is streamed into the app so that --max-body-size can stop reading early.
+    let (mut parts, ()) = req.into_parts();
+    parts.extensions.insert(peer);
+    let req = Request::from_parts(parts, Body::new(RecvBody(recv)));
+
+    let resp = app.oneshot(req).await?;
+    let (parts, mut body) = resp.into_parts();
+
+    send.send_response(Response::from_parts(parts, ())).await?;
+    while let Some(frame) = body.frame().await {
+        match frame?.into_data() {
+            Ok(data) if !data.is_empty() => send.send_data(data).await?,
+            Ok(_) => {}
+            Err(frame) => {
+                if let Ok(trailers) = frame.into_trailers() {
+                    send.send_trailers(trailers).await?;
+                }
+            }
+        }
+    }
+    send.finish().await?;
+    Ok(())
+}
+
+/// Request body read from the HTTP/3 stream as the app asks for it
+struct RecvBody(RequestStream<h3_quinn::RecvStream, Bytes>);
+
+impl http_body::Body for RecvBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match ready!(self.0.poll_recv_data(cx)) {
+            Ok(Some(mut chunk)) => {
+                let chunk = chunk.copy_to_bytes(chunk.remaining());
+                Poll::Ready(Some(Ok(Frame::data(chunk))))
+            }
+            Ok(None) => Poll::Ready(None),
+            Err(err) => Poll::Ready(Some(Err(axum::Error::new(err)))),
+        }
+    }
+}
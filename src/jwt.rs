@@ -0,0 +1,204 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use aws_lc_rs::{
+    rand::SystemRandom,
+    rsa::PublicKeyComponents,
+    signature::{
+        EcdsaKeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING, RSA_PKCS1_SHA256,
+    },
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, KeyInit, Mac};
+use rustls::pki_types::{pem::PemObject, PrivateKeyDer};
+use sha2::{Digest, Sha256};
+use tera::{Kwargs, Map, State, Tera, TeraResult};
+
+/// Private key for signing RS256 or ES256 tokens
+pub enum SigningKey {
+    Rsa(RsaKeyPair),
+    Ec(EcdsaKeyPair),
+}
+
+impl SigningKey {
+    /// Parse an RSA or P-256 private key in PKCS#1, SEC1 or PKCS#8 PEM format
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        let der = PrivateKeyDer::from_pem_slice(pem).context("Expected a PEM private key")?;
+        let key = match &der {
+            PrivateKeyDer::Pkcs1(der) => RsaKeyPair::from_der(der.secret_pkcs1_der())
+                .map(Self::Rsa)
+                .map_err(|e| anyhow!("Invalid RSA key: {e}"))?,
+            PrivateKeyDer::Sec1(der) => EcdsaKeyPair::from_private_key_der(
+                &ECDSA_P256_SHA256_FIXED_SIGNING,
+                der.secret_sec1_der(),
+            )
+            .map(Self::Ec)
+            .map_err(|e| anyhow!("Invalid EC key, only P-256 is supported: {e}"))?,
+            PrivateKeyDer::Pkcs8(der) => {
+                let der = der.secret_pkcs8_der();
+                RsaKeyPair::from_pkcs8(der)
+                    .map(Self::Rsa)
+                    .or_else(|_| {
+                        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, der)
+                            .map(Self::Ec)
+                    })
+                    .map_err(|e| anyhow!("Only RSA and P-256 keys are supported: {e}"))?
+            }
+            _ => return Err(anyhow!("Unsupported private key format")),
+        };
+        Ok(key)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|pem| Self::from_pem(&pem))
+            .with_context(|| format!("Failed to load JWT key from {}", path.display()))
+    }
+
+    pub fn alg(&self) -> &'static str {
+        match self {
+            Self::Rsa(_) => "RS256",
+            Self::Ec(_) => "ES256",
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> TeraResult<Vec<u8>> {
+        let failed = |_| tera::Error::message("Failed to sign the JWT");
+        match self {
+            Self::Rsa(key) => {
+                let mut signature = vec![0; key.public_modulus_len()];
+                key.sign(
+                    &RSA_PKCS1_SHA256,
+                    &SystemRandom::new(),
+                    message,
+                    &mut signature,
+                )
+                .map_err(failed)?;
+                Ok(signature)
+            }
+            Self::Ec(key) => {
+                let signature = key.sign(&SystemRandom::new(), message).map_err(failed)?;
+                Ok(signature.as_ref().to_vec())
+            }
+        }
+    }
+
+    /// The public key's required JWK members, which also make up its RFC 7638 thumbprint
+    fn public_jwk(&self) -> serde_json::Value {
+        match self {
+            Self::Rsa(key) => {
+                let components = PublicKeyComponents::<Vec<u8>>::from(key.public_key());
+                serde_json::json!({
+                    "kty": "RSA",
+                    "n": URL_SAFE_NO_PAD.encode(components.n),
+                    "e": URL_SAFE_NO_PAD.encode(components.e),
+                })
+            }
+            Self::Ec(key) => {
+                // Uncompressed point: 0x04 followed by both coordinates
+                let point = key.public_key().as_ref();
+                serde_json::json!({
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                    "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+                })
+            }
+        }
+    }
+
+    /// Key ID, the RFC 7638 thumbprint of the public key
+    fn kid(&self) -> String {
+        // serde_json sorts the members and doesn't add whitespace, just like RFC 7638 asks for.
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.public_jwk().to_string()))
+    }
+
+    /// JWK Set containing the public key, as served via --jwks-path
+    pub fn jwks(&self) -> serde_json::Value {
+        let mut jwk = self.public_jwk();
+        jwk["kid"] = self.kid().into();
+        jwk["use"] = "sig".into();
+        jwk["alg"] = self.alg().into();
+        serde_json::json!({ "keys": [jwk] })
+    }
+}
+
+/// Register `jwt()`, signing RS256/ES256 tokens with `key` unless given one
+pub fn register_function(tera: &mut Tera, key: Option<Arc<SigningKey>>) {
+    tera.register_function("jwt", move |kwargs: Kwargs, _: &State<'_>| {
+        jwt(&kwargs, key.as_deref())
+    });
+}
+
+/// A signed JWT carrying `claims`
+///
+/// HS256 tokens are signed with the secret given as `key`, RS256/ES256 tokens with the PEM key
+/// given as `key` or the one from --jwt-key.
+fn jwt(kwargs: &Kwargs, configured_key: Option<&SigningKey>) -> TeraResult<String> {
+    let claims = kwargs.must_get::<&Map>("claims")?;
+    let key = kwargs.get::<&str>("key")?;
+    let default_alg = match (key, configured_key) {
+        (None, Some(configured_key)) => configured_key.alg(),
+        _ => "HS256",
+    };
+    let alg = kwargs.get::<&str>("alg")?.unwrap_or(default_alg);
+
+    let inline_key;
+    let signing_key = match (alg, key) {
+        ("HS256", _) => None,
+        ("RS256" | "ES256", Some(pem)) => {
+            inline_key = SigningKey::from_pem(pem.as_bytes())
+                .map_err(|e| tera::Error::message(format!("jwt() got an invalid key: {e:#}")))?;
+            Some((&inline_key, None))
+        }
+        ("RS256" | "ES256", None) => {
+            let configured_key = configured_key.ok_or_else(|| {
+                tera::Error::message(format!("jwt() needs a key or --jwt-key for {alg}"))
+            })?;
+            Some((configured_key, Some(configured_key.kid())))
+        }
+        _ => {
+            return Err(tera::Error::message(format!(
+                "jwt() supports HS256, RS256 and ES256, not {alg}"
+            )))
+        }
+    };
+
+    let mut header = serde_json::json!({ "alg": alg, "typ": "JWT" });
+    if let Some((_, Some(kid))) = &signing_key {
+        header["kid"] = kid.as_str().into();
+    }
+    // Going through serde_json sorts the claims so the same claims always make the same token.
+    let claims = serde_json::to_value(claims)
+        .map_err(|e| tera::Error::message(format!("jwt() got invalid claims: {e}")))?;
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+
+    let signature = match signing_key {
+        None => {
+            let secret = key.ok_or_else(|| tera::Error::message("jwt() needs a key for HS256"))?;
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+                .map_err(|e| tera::Error::message(format!("Invalid HMAC key: {e}")))?;
+            mac.update(signing_input.as_bytes());
+            mac.finalize().into_bytes().to_vec()
+        }
+        Some((signing_key, _)) => {
+            if signing_key.alg() != alg {
+                return Err(tera::Error::message(format!(
+                    "jwt() got a {} key for {alg}",
+                    signing_key.alg()
+                )));
+            }
+            signing_key.sign(signing_input.as_bytes())?
+        }
+    };
+    Ok(format!(
+        "{signing_input}.{}",
+        URL_SAFE_NO_PAD.encode(signature)
+    ))
+}
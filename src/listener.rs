@@ -0,0 +1,457 @@
+use std::fmt;
+#[cfg(feature = "http3")]
+use std::net::UdpSocket;
+use std::net::{IpAddr, SocketAddr, TcpListener, ToSocketAddrs};
+use std::path::Path;
+#[cfg(unix)]
+use std::{
+    env, fs,
+    os::{
+        fd::FromRawFd,
+        unix::{
+            fs::{FileTypeExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+    },
+};
+
+use anyhow::{bail, Context, Result};
+#[cfg(feature = "http3")]
+use axum::Extension;
+use axum::Router;
+#[cfg(feature = "tls")]
+use axum_server::tls_rustls::{RustlsAcceptor, RustlsConfig};
+use axum_server::{Address, Server};
+use serde_json::{json, Value};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::task::JoinSet;
+
+use crate::args::{Args, ListenSpec, Scheme};
+use crate::peer::PeerAcceptor;
+#[cfg(feature = "http3")]
+use crate::{http3, http3::AltSvc, tls};
+
+/// A bound socket we're about to serve on
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(Scheme, TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+    /// HTTP/3 next to an HTTPS listener on the same port
+    #[cfg(feature = "http3")]
+    Quic(UdpSocket),
+}
+
+impl fmt::Display for Listener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Listener::Tcp(scheme, socket) => match socket.local_addr() {
+                Ok(addr) => write!(f, "{scheme}://{addr}"),
+                Err(_) => write!(f, "{scheme}://<unknown>"),
+            },
+            #[cfg(unix)]
+            Listener::Unix(socket) => {
+                let path = socket.local_addr().ok();
+                let path = path.as_ref().and_then(|addr| addr.as_pathname());
+                match path {
+                    Some(path) => write!(f, "unix:{}", path.to_string_lossy()),
+                    None => write!(f, "unix:<unknown>"),
+                }
+            }
+            #[cfg(feature = "http3")]
+            Listener::Quic(socket) => match socket.local_addr() {
+                Ok(addr) => write!(f, "quic://{addr}"),
+                Err(_) => write!(f, "quic://<unknown>"),
+            },
+        }
+    }
+}
+
+impl Listener {
+    /// Describe this listener in a machine-readable way
+    fn to_json(&self) -> Value {
+        match self {
+            Listener::Tcp(scheme, socket) => {
+                let addr = socket.local_addr().ok();
+                json!({
+                    "url": self.to_string(),
+                    "scheme": scheme.to_string(),
+                    "ip": addr.map(|addr| addr.ip().to_string()),
+                    "port": addr.map(|addr| addr.port()),
+                })
+            }
+            #[cfg(unix)]
+            Listener::Unix(socket) => {
+                let path = socket.local_addr().ok();
+                let path = path.as_ref().and_then(|addr| addr.as_pathname());
+                json!({
+                    "url": self.to_string(),
+                    "scheme": "unix",
+                    "path": path.map(|path| path.to_string_lossy()),
+                })
+            }
+            #[cfg(feature = "http3")]
+            Listener::Quic(socket) => {
+                let addr = socket.local_addr().ok();
+                json!({
+                    "url": self.to_string(),
+                    "scheme": "quic",
+                    "ip": addr.map(|addr| addr.ip().to_string()),
+                    "port": addr.map(|addr| addr.port()),
+                })
+            }
+        }
+    }
+}
+
+/// Describe all bound `listeners` so that test harnesses know where to find us
+pub fn readiness_json(listeners: &[Listener]) -> Value {
+    json!({
+        "pid": std::process::id(),
+        "listeners": listeners.iter().map(Listener::to_json).collect::<Vec<_>>(),
+    })
+}
+
+/// Write `readiness` to `path`
+///
+/// The file is written under a temporary name first and then moved into place so that whoever
+/// waits for it never sees it half-written.
+pub fn write_ready_file(path: &Path, readiness: &Value) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    std::fs::write(&tmp_path, format!("{readiness}\n"))
+        .and_then(|_| std::fs::rename(&tmp_path, path))
+        .with_context(|| format!("Failed to write ready file '{}'", path.to_string_lossy()))
+}
+
+/// Bind a TCP socket on `addr`
+///
+/// IPv6 sockets are made dual-stack unless `only_v6` is given, in which case they only accept
+/// IPv6 connections so that IPv4 can be bound separately on the same port.
+fn bind_tcp(addr: SocketAddr, only_v6: bool) -> Result<TcpListener> {
+    let bind = || -> std::io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(only_v6)?;
+        }
+        #[cfg(not(windows))]
+        socket.set_reuse_address(true)?;
+        socket.bind(&addr.into())?;
+        socket.listen(1024)?;
+        socket.set_nonblocking(true)?;
+        Ok(socket.into())
+    };
+    bind().with_context(|| format!("Failed to bind to {addr}"))
+}
+
+/// Bind a UDP socket for QUIC on `addr`
+///
+/// See `bind_tcp` for `only_v6`.
+#[cfg(feature = "http3")]
+fn bind_udp(addr: SocketAddr, only_v6: bool) -> Result<UdpSocket> {
+    let bind = || -> std::io::Result<UdpSocket> {
+        let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+        if addr.is_ipv6() {
+            socket.set_only_v6(only_v6)?;
+        }
+        socket.bind(&addr.into())?;
+        Ok(socket.into())
+    };
+    bind().with_context(|| format!("Failed to bind to {addr} (UDP)"))
+}
+
+/// Bind a QUIC socket on the same address as every HTTPS listener
+#[cfg(feature = "http3")]
+fn bind_quic(listeners: &[Listener]) -> Result<Vec<Listener>> {
+    let addresses = listeners
+        .iter()
+        .filter_map(|listener| match listener {
+            Listener::Tcp(Scheme::Https, socket) => socket.local_addr().ok(),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    addresses
+        .iter()
+        .map(|&addr| {
+            let only_v6 = addresses
+                .iter()
+                .any(|other| other.is_ipv4() && other.port() == addr.port());
+            Ok(Listener::Quic(bind_udp(addr, only_v6)?))
+        })
+        .collect()
+}
+
+/// Remove a socket file left behind by a previous run
+///
+/// We only remove actual sockets nobody is listening on anymore.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        bail!("'{}' exists and is not a socket", path.to_string_lossy());
+    }
+    if UnixStream::connect(path).is_ok() {
+        bail!("'{}' is already in use", path.to_string_lossy());
+    }
+    fs::remove_file(path)
+        .with_context(|| format!("Failed to remove stale socket '{}'", path.to_string_lossy()))
+}
+
+/// Bind a Unix socket on `path`, optionally setting its permissions to `mode`
+#[cfg(unix)]
+fn bind_unix(path: &Path, mode: Option<u32>) -> Result<UnixListener> {
+    remove_stale_socket(path)?;
+
+    let socket = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind to '{}'", path.to_string_lossy()))?;
+    socket.set_nonblocking(true)?;
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).with_context(|| {
+            format!("Failed to set permissions of '{}'", path.to_string_lossy())
+        })?;
+    }
+    Ok(socket)
+}
+
+/// First file descriptor passed by systemd socket activation
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// Take over the sockets passed to us via systemd socket activation
+///
+/// See sd_listen_fds(3). Sockets named `https` or `tls` in `LISTEN_FDNAMES` speak TLS, all others
+/// plain HTTP.
+#[cfg(unix)]
+fn inherit_listeners(tls: bool) -> Result<Vec<Listener>> {
+    let for_us = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .is_some_and(|pid| pid == std::process::id());
+    if !for_us {
+        return Ok(vec![]);
+    }
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<i32>().ok())
+        .context("LISTEN_PID is set but LISTEN_FDS is missing or invalid")?;
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let names = names.split(':').collect::<Vec<_>>();
+
+    let mut listeners = vec![];
+    for (i, fd) in (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + count).enumerate() {
+        let name = names.get(i).copied().unwrap_or_default();
+
+        // SAFETY: systemd hands these file descriptors over to us and nothing else in this
+        // process uses them.
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        socket.set_nonblocking(true)?;
+        let addr = socket
+            .local_addr()
+            .with_context(|| format!("Inherited file descriptor {fd} is not a socket"))?;
+
+        if addr.is_unix() {
+            listeners.push(Listener::Unix(socket.into()));
+        } else if matches!(name, "https" | "tls") {
+            if !tls {
+                bail!("Inherited socket '{name}' is meant for TLS which requires --tls-cert");
+            }
+            listeners.push(Listener::Tcp(Scheme::Https, socket.into()));
+        } else {
+            listeners.push(Listener::Tcp(Scheme::Http, socket.into()));
+        }
+    }
+    Ok(listeners)
+}
+
+/// Resolve the TCP addresses we should listen on from the command line
+///
+/// Without any --listen given, we listen on --interface and --port using HTTPS if certificates
+/// were given and HTTP otherwise.
+fn resolve_addresses(args: &Args, tls: bool) -> Result<Vec<(Scheme, SocketAddr)>> {
+    if args.listen.is_empty() {
+        let scheme = if tls { Scheme::Https } else { Scheme::Http };
+        return Ok(vec![(
+            scheme,
+            SocketAddr::from((args.interface, args.port)),
+        )]);
+    }
+
+    let mut addresses = vec![];
+    for spec in &args.listen {
+        let ListenSpec::Tcp { scheme, host, port } = spec else {
+            continue;
+        };
+        if *scheme == Scheme::Https && !tls {
+            bail!("Listening on https:// requires --tls-cert");
+        }
+        let resolved = if let Ok(ip) = host.parse::<IpAddr>() {
+            vec![SocketAddr::from((ip, *port))]
+        } else {
+            (host.as_str(), *port)
+                .to_socket_addrs()
+                .with_context(|| format!("Failed to resolve '{host}'"))?
+                .collect()
+        };
+        for addr in resolved {
+            if !addresses.contains(&(*scheme, addr)) {
+                addresses.push((*scheme, addr));
+            }
+        }
+    }
+    Ok(addresses)
+}
+
+/// Bind all the sockets we should listen on
+///
+/// Sockets inherited via systemd socket activation are used instead of --interface and --port
+/// but in addition to any --listen addresses. With HTTP/3 support, every HTTPS listener is
+/// accompanied by a QUIC listener on the same port.
+pub fn bind(args: &Args, tls: bool) -> Result<Vec<Listener>> {
+    #[cfg(unix)]
+    let inherited = inherit_listeners(tls)?;
+    #[cfg(not(unix))]
+    let inherited = vec![];
+    let mut listeners = inherited;
+    if listeners.is_empty() || !args.listen.is_empty() {
+        listeners.extend(bind_configured(args, tls)?);
+    }
+
+    #[cfg(feature = "http3")]
+    if !args.http1_only && !args.http2_only {
+        listeners.extend(bind_quic(&listeners)?);
+    }
+
+    Ok(listeners)
+}
+
+/// Bind the sockets given via --listen or --interface and --port
+fn bind_configured(args: &Args, tls: bool) -> Result<Vec<Listener>> {
+    let addresses = resolve_addresses(args, tls)?;
+
+    let mut listeners = addresses
+        .iter()
+        .map(|&(scheme, addr)| {
+            let only_v6 = addresses
+                .iter()
+                .any(|(_, other)| other.is_ipv4() && other.port() == addr.port());
+            Ok(Listener::Tcp(scheme, bind_tcp(addr, only_v6)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    for spec in &args.listen {
+        let ListenSpec::Unix(path) = spec else {
+            continue;
+        };
+        #[cfg(unix)]
+        listeners.push(Listener::Unix(bind_unix(path, args.unix_socket_mode)?));
+        #[cfg(not(unix))]
+        bail!(
+            "Unix sockets are not supported on this platform ('{}')",
+            path.to_string_lossy()
+        );
+    }
+
+    Ok(listeners)
+}
+
+/// Describe which HTTP versions we restrict ourselves to, if any
+pub fn version_restrictions(args: &Args) -> Vec<&'static str> {
+    let mut restrictions = vec![];
+    if args.http1_only {
+        restrictions.push("HTTP/1 only");
+    }
+    if args.http2_only {
+        restrictions.push("HTTP/2 only");
+    }
+    if args.reject_http10 {
+        restrictions.push("rejecting HTTP/1.0");
+    }
+    restrictions
+}
+
+/// Restrict `server` to the HTTP versions given on the command line
+fn restrict_versions<A: Address, Acc>(server: Server<A, Acc>, args: &Args) -> Server<A, Acc> {
+    if args.http1_only {
+        server.http1_only()
+    } else if args.http2_only {
+        server.http2_only()
+    } else {
+        server
+    }
+}
+
+/// Serve `app` on all `listeners` concurrently until one of them fails
+pub async fn serve(
+    listeners: Vec<Listener>,
+    app: Router,
+    args: &Args,
+    #[cfg(feature = "tls")] tls_config: Option<RustlsConfig>,
+) -> Result<()> {
+    // Only advertise HTTP/3 where we're actually listening for it.
+    #[cfg(feature = "http3")]
+    let quic_addresses = listeners
+        .iter()
+        .filter_map(|listener| match listener {
+            Listener::Quic(socket) => socket.local_addr().ok(),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let make_service = app.clone().into_make_service();
+        match listener {
+            Listener::Tcp(Scheme::Http, socket) => {
+                let acceptor = PeerAcceptor::new(args.proxy_protocol);
+                let server = axum_server::from_tcp(socket)?.acceptor(acceptor);
+                let server = restrict_versions(server, args);
+                servers.spawn(server.serve(make_service));
+            }
+            #[cfg(feature = "tls")]
+            Listener::Tcp(Scheme::Https, socket) => {
+                let tls_config = tls_config
+                    .clone()
+                    .context("Listening on https:// requires --tls-cert")?;
+                #[cfg(feature = "http3")]
+                let make_service = {
+                    let addr = socket.local_addr()?;
+                    if quic_addresses.contains(&addr) {
+                        let alt_svc = AltSvc::for_port(addr.port());
+                        app.clone().layer(Extension(alt_svc)).into_make_service()
+                    } else {
+                        make_service
+                    }
+                };
+                let acceptor = RustlsAcceptor::new(tls_config)
+                    .acceptor(PeerAcceptor::new(args.proxy_protocol));
+                let server = axum_server::from_tcp(socket)?.acceptor(acceptor);
+                let server = restrict_versions(server, args);
+                servers.spawn(server.serve(make_service));
+            }
+            #[cfg(not(feature = "tls"))]
+            Listener::Tcp(Scheme::Https, _) => bail!("dummyhttp was built without TLS support"),
+            #[cfg(unix)]
+            Listener::Unix(socket) => {
+                let server = axum_server::from_unix(socket)?.acceptor(PeerAcceptor::default());
+                let server = restrict_versions(server, args);
+                servers.spawn(server.serve(make_service));
+            }
+            #[cfg(feature = "http3")]
+            Listener::Quic(socket) => {
+                let tls_config = tls_config
+                    .as_ref()
+                    .context("Listening on QUIC requires --tls-cert")?;
+                let config = tls::quic_server_config(tls_config)?;
+                servers.spawn(http3::serve(socket, config, app.clone()));
+            }
+        }
+    }
+
+    while let Some(result) = servers.join_next().await {
+        result??;
+    }
+    Ok(())
+}
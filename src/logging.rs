@@ -0,0 +1,214 @@
+use axum::http::{HeaderMap, StatusCode};
+use chrono::{DateTime, Local};
+use tokio::time::Duration;
+
+use crate::args::LogFormat;
+use crate::encoding::DisplayBody;
+use crate::peer::Peer;
+
+/// Everything we know about a request and the response we sent for it
+pub struct Exchange<'a> {
+    pub time: DateTime<Local>,
+    pub peer: &'a Peer,
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub http_version: &'a str,
+    pub req_headers: &'a HeaderMap,
+    pub req_body: &'a DisplayBody,
+    pub status: StatusCode,
+    pub resp_headers: &'a HeaderMap,
+    pub resp_body: &'a DisplayBody,
+    pub latency: Duration,
+    /// What went wrong if we failed to produce the configured response
+    pub error: Option<&'a str>,
+}
+
+/// Print a message that isn't part of the request log, such as about reloading things
+///
+/// Goes to stderr with the machine-readable log formats to keep stdout parseable.
+pub fn print_message(format: LogFormat, message: &str) {
+    if format == LogFormat::Pretty {
+        println!("{message}");
+    } else {
+        eprintln!("{message}");
+    }
+}
+
+/// Format `exchange` as a single line in one of the machine-readable log formats
+///
+/// Like the pretty log, headers are included from one `-v` on and bodies from `-vv` on.
+pub fn format_line(exchange: &Exchange, format: LogFormat, verbose: u8) -> String {
+    match format {
+        LogFormat::Pretty => unreachable!("the pretty log spans multiple lines"),
+        LogFormat::Json => json_line(exchange, verbose),
+        LogFormat::Logfmt => logfmt_line(exchange, verbose),
+        LogFormat::Combined => combined_line(exchange),
+    }
+}
+
+fn latency_ms(latency: Duration) -> f64 {
+    latency.as_secs_f64() * 1000.0
+}
+
+/// Headers as a JSON object, joining repeated headers with ", "
+fn headers_json(headers: &HeaderMap) -> serde_json::Value {
+    let mut map = serde_json::Map::new();
+    for name in headers.keys() {
+        let values = headers
+            .get_all(name)
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).to_string())
+            .collect::<Vec<_>>();
+        map.insert(name.to_string(), values.join(", ").into());
+    }
+    map.into()
+}
+
+/// Add the size of `body` and, if it was compressed, how decompressing it went to `json`
+fn body_size_json(json: &mut serde_json::Value, body: &DisplayBody) {
+    json["body_size"] = body.wire_size.into();
+    if let Some(sha256) = &body.sha256 {
+        json["body_truncated"] = true.into();
+        json["body_sha256"] = sha256.as_str().into();
+    }
+    if body.encoding.is_none() {
+        return;
+    }
+    match &body.error {
+        Some(error) => json["decompression_error"] = error.as_str().into(),
+        None => json["decompressed_body_size"] = body.bytes.len().into(),
+    }
+}
+
+fn json_line(exchange: &Exchange, verbose: u8) -> String {
+    let mut request = serde_json::json!({
+        "method": exchange.method,
+        "uri": exchange.uri,
+        "version": format!("HTTP/{}", exchange.http_version),
+    });
+    let mut response = serde_json::json!({
+        "status": exchange.status.as_u16(),
+    });
+    body_size_json(&mut request, exchange.req_body);
+    body_size_json(&mut response, exchange.resp_body);
+    if verbose >= 1 {
+        request["headers"] = headers_json(exchange.req_headers);
+        response["headers"] = headers_json(exchange.resp_headers);
+    }
+    if verbose >= 2 {
+        request["body"] = String::from_utf8_lossy(&exchange.req_body.bytes).into();
+        response["body"] = String::from_utf8_lossy(&exchange.resp_body.bytes).into();
+    }
+
+    let mut line = serde_json::json!({
+        "time": exchange.time.to_rfc3339(),
+        "peer": exchange.peer.to_string(),
+        "request": request,
+        "response": response,
+        "latency_ms": latency_ms(exchange.latency),
+    });
+    if let Some(error) = exchange.error {
+        line["error"] = error.into();
+    }
+    line.to_string()
+}
+
+/// Quote a logfmt value if it contains anything that would break up the line
+fn logfmt_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '=' || c == '"');
+    if needs_quotes {
+        format!("{value:?}")
+    } else {
+        value.to_string()
+    }
+}
+
+fn logfmt_line(exchange: &Exchange, verbose: u8) -> String {
+    let mut fields = vec![
+        ("time".to_string(), exchange.time.to_rfc3339()),
+        ("peer".to_string(), exchange.peer.to_string()),
+        ("method".to_string(), exchange.method.to_string()),
+        ("uri".to_string(), exchange.uri.to_string()),
+        (
+            "version".to_string(),
+            format!("HTTP/{}", exchange.http_version),
+        ),
+        ("status".to_string(), exchange.status.as_u16().to_string()),
+        (
+            "latency_ms".to_string(),
+            format!("{:.3}", latency_ms(exchange.latency)),
+        ),
+        (
+            "req_body_size".to_string(),
+            exchange.req_body.wire_size.to_string(),
+        ),
+        (
+            "resp_body_size".to_string(),
+            exchange.resp_body.wire_size.to_string(),
+        ),
+    ];
+    if let Some(error) = exchange.error {
+        fields.push(("error".to_string(), error.to_string()));
+    }
+    if verbose >= 1 {
+        for (prefix, headers) in [
+            ("req_header", exchange.req_headers),
+            ("resp_header", exchange.resp_headers),
+        ] {
+            for (name, value) in headers {
+                let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+                fields.push((format!("{prefix}.{name}"), value));
+            }
+        }
+    }
+    if verbose >= 2 {
+        for (key, body) in [
+            ("req_body", exchange.req_body),
+            ("resp_body", exchange.resp_body),
+        ] {
+            let body = String::from_utf8_lossy(&body.bytes).to_string();
+            fields.push((key.to_string(), body));
+        }
+    }
+
+    fields
+        .iter()
+        .map(|(key, value)| format!("{key}={}", logfmt_value(value)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Apache's combined log format with the latency in microseconds (`%D`) appended
+fn combined_line(exchange: &Exchange) -> String {
+    let host = match exchange.peer {
+        Peer::Ip(addr) => addr.ip().to_string(),
+        #[cfg(unix)]
+        Peer::Unix(_) => "-".to_string(),
+    };
+    let header = |name: &str| {
+        exchange
+            .req_headers
+            .get(name)
+            .map(|value| format!("{:?}", String::from_utf8_lossy(value.as_bytes())))
+            .unwrap_or_else(|| "\"-\"".to_string())
+    };
+    let size = match exchange.resp_body.wire_size {
+        0 => "-".to_string(),
+        size => size.to_string(),
+    };
+
+    format!(
+        "{host} - - [{time}] \"{method} {uri} HTTP/{version}\" {status} {size} {referer} {user_agent} {latency}",
+        time = exchange.time.format("%d/%b/%Y:%H:%M:%S %z"),
+        method = exchange.method,
+        uri = exchange.uri,
+        version = exchange.http_version,
+        status = exchange.status.as_u16(),
+        referer = header("referer"),
+        user_agent = header("user-agent"),
+        latency = exchange.latency.as_micros(),
+    )
+}
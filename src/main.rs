@@ -1,93 +1,158 @@
-use std::net::SocketAddr;
-
-#[cfg(not(feature = "tls"))]
 use anyhow::Result;
-#[cfg(feature = "tls")]
-use anyhow::{Context, Result};
 
 use axum::{
-    body::{Body, Bytes},
-    extract::{ConnectInfo, Request},
-    http::{HeaderValue, StatusCode, Uri},
+    body::Body,
+    extract::{DefaultBodyLimit, Request, State},
+    http::{HeaderValue, Method, StatusCode, Uri, Version},
     middleware::{self, Next},
     response::{IntoResponse, Response},
-    Extension, Router,
+    routing::get,
+    Extension, Json, Router,
 };
 
-#[cfg(feature = "tls")]
-use axum_server::tls_rustls::RustlsConfig;
-use chrono::Local;
 use clap::{crate_version, CommandFactory, Parser};
 use colored::*;
-use colored_json::ToColoredJson;
-use hyper::{header::CONTENT_TYPE, HeaderMap};
+use hyper::HeaderMap;
 use inflector::Inflector;
-use tokio::time::{sleep, Duration};
-
-use crate::args::Args;
+use tokio::time::{sleep, Duration, Instant};
 
-mod args;
-
-pub fn template_now(
-    _kwargs: tera::Kwargs,
-    _state: &tera::State<'_>,
-) -> tera::TeraResult<tera::Value> {
-    Ok(tera::Value::from(chrono::Utc::now().to_rfc3339()))
-}
+use std::sync::{Arc, Mutex};
 
-pub fn template_uuid(
-    _kwargs: tera::Kwargs,
-    _state: &tera::State<'_>,
-) -> tera::TeraResult<tera::Value> {
-    Ok(tera::Value::from(uuid::Uuid::new_v4().to_string()))
-}
+use crate::args::{Args, LogFormat};
+use crate::body::{Capture, CapturingBody, GeneratedBody};
+use crate::clock::Clock;
+use crate::jwt::SigningKey;
+use crate::logging::Exchange;
+use crate::peer::Peer;
+use crate::template::{RenderError, Rendered, RequestInfo, Templates};
 
-pub fn template_lorem(
-    kwargs: tera::Kwargs,
-    _state: &tera::State<'_>,
-) -> tera::TeraResult<tera::Value> {
-    let n_words = kwargs
-        .get::<u64>("words")?
-        .ok_or_else(|| tera::Error::message("Failed to template lorem"))?;
-    Ok(tera::Value::from(lipsum::lipsum(n_words as usize)))
+mod args;
+mod body;
+mod clock;
+mod encoding;
+mod faker;
+mod filters;
+#[cfg(feature = "http3")]
+mod http3;
+mod jwt;
+mod listener;
+mod logging;
+mod peer;
+mod pretty;
+mod proxy;
+mod template;
+#[cfg(feature = "tls")]
+mod tls;
+mod watch;
+
+/// State shared by all requests
+pub struct AppState {
+    pub args: Args,
+    pub templates: Templates,
+    pub clock: Clock,
+    pub jwt_key: Option<Arc<SigningKey>>,
 }
 
 /// dummyhttp only has a single response and this is it :)
-async fn dummy_response(_uri: Uri, Extension(args): Extension<Args>) -> impl IntoResponse {
-    let status_code = StatusCode::from_u16(args.code).unwrap();
+async fn dummy_response(
+    Extension(peer): Extension<Peer>,
+    method: Method,
+    uri: Uri,
+    req_headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+    #[cfg(feature = "http3")] alt_svc: Option<Extension<http3::AltSvc>>,
+    req_body: Body,
+) -> Response {
+    let args = &state.args;
+    let keep = if state.templates.uses_request() {
+        usize::try_from(args.template_body_limit).unwrap_or(usize::MAX)
+    } else {
+        0
+    };
+    let req_body =
+        match body::read_request_body(req_body, &req_headers, args.max_body_size, keep).await {
+            Ok(req_body) => req_body,
+            Err(rejection) => return rejection.into_response(),
+        };
+    let request = RequestInfo {
+        peer: &peer,
+        method: &method,
+        uri: &uri,
+        headers: &req_headers,
+        body: &req_body,
+    };
+    let Rendered {
+        status: status_code,
+        mut headers,
+        body: rendered_body,
+    } = match state.templates.render(&request) {
+        Ok(rendered) => rendered,
+        Err(err) => {
+            let status = StatusCode::from_u16(args.template_error_code).unwrap();
+            return err.into_response(status);
+        }
+    };
 
-    let mut headers = HeaderMap::new();
-    for header in &args.headers {
-        let val = header.iter().next().unwrap();
-        headers.insert(val.0.clone(), val.1.clone());
+    #[cfg(feature = "http3")]
+    if let Some(Extension(http3::AltSvc(alt_svc))) = alt_svc {
+        headers.insert(hyper::header::ALT_SVC, alt_svc);
     }
 
     // Manually insert a Date header here so that our log print will catch it later on as the
     // date is inserted _after_ logging otherwise.
-    let time = Local::now();
+    let time = state.clock.now();
     headers.insert("date", HeaderValue::from_str(&time.to_rfc2822()).unwrap());
 
-    // Render body as Tera template.
-    let mut tera = tera::Tera::default();
-    tera.register_function("now", template_now);
-    tera.register_function("uuid", template_uuid);
-    tera.register_function("lorem", template_lorem);
-    let rendered_body = tera
-        .render_str(&args.body, &tera::Context::new(), false)
-        .unwrap();
+    let body = match args.body_size {
+        Some(size) => Body::new(GeneratedBody::new(
+            args.body_pattern,
+            size,
+            &rendered_body,
+            args.body_seed,
+            !args.chunked,
+        )),
+        None => Body::from(rendered_body),
+    };
 
     // Delay response.
     sleep(Duration::from_millis(args.delay)).await;
 
-    (status_code, headers, rendered_body)
+    (status_code, headers, body).into_response()
+}
+
+/// Serve the public key of --jwt-key as JWK Set
+async fn jwks(State(state): State<Arc<AppState>>) -> Response {
+    match &state.jwt_key {
+        Some(key) => Json(key.jwks()).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Reject requests using HTTP versions we were told not to accept
+///
+/// This runs inside `print_request_response` so that rejected requests are logged as well.
+async fn reject_http_versions(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if state.args.reject_http10 && req.version() == Version::HTTP_10 {
+        return (
+            StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+            "HTTP/1.0 is not supported",
+        )
+            .into_response();
+    }
+    next.run(req).await
 }
 
 async fn print_request_response(
+    State(state): State<Arc<AppState>>,
     req: Request,
     next: Next,
-) -> Result<impl IntoResponse, (StatusCode, String)> {
-    let args = req.extensions().get::<Args>().unwrap().clone();
-    let ConnectInfo(peer_info) = *req.extensions().get::<ConnectInfo<SocketAddr>>().unwrap();
+) -> Response {
+    let args = &state.args;
+    let peer_info = req.extensions().get::<Peer>().unwrap().clone();
     let method = req.method().to_string();
     let uri = req.uri().to_string();
     let http_version = format!("{:?}", req.version())
@@ -97,14 +162,70 @@ async fn print_request_response(
         .to_string();
     let req_headers = req.headers().clone();
 
-    let (parts, body) = req.into_parts();
-    let bytes = buffer_and_print("request", body).await?;
-    let bytes2 = bytes.clone();
-    let req = Request::from_parts(parts, Body::from(bytes));
-
+    let log_limit = usize::try_from(args.log_body_limit).unwrap_or(usize::MAX);
+    let start = Instant::now();
+    // The handler reads the body so we only get to log it once it's done with it.
+    let req_captured = Arc::new(Mutex::new(None));
+    let req = req.map(|body| {
+        let req_captured = req_captured.clone();
+        Body::new(CapturingBody::new(body, log_limit, move |captured| {
+            *req_captured.lock().unwrap() = Some(captured);
+        }))
+    });
     let resp = next.run(req).await;
+    let captured = req_captured
+        .lock()
+        .unwrap()
+        .take()
+        .unwrap_or_else(|| Capture::new(0).finish());
+
+    // Streamed bodies might take a while so we log once the response has been sent.
+    let (parts, body) = resp.into_parts();
+    let status = parts.status;
+    let resp_headers = parts.headers.clone();
+    let error = parts
+        .extensions
+        .get::<RenderError>()
+        .map(|err| err.0.clone());
+    let body = CapturingBody::new(body, log_limit, move |resp_captured| {
+        let req_body = encoding::decode(captured, &req_headers);
+        let resp_body = encoding::decode(resp_captured, &resp_headers);
+        let exchange = Exchange {
+            time: state.clock.now(),
+            peer: &peer_info,
+            method: &method,
+            uri: &uri,
+            http_version: &http_version,
+            req_headers: &req_headers,
+            req_body: &req_body,
+            status,
+            resp_headers: &resp_headers,
+            resp_body: &resp_body,
+            latency: start.elapsed(),
+            error: error.as_deref(),
+        };
+        print_exchange(&state.args, &exchange);
+    });
+
+    Response::from_parts(parts, Body::new(body))
+}
 
-    let time = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
+/// Print a request and our response to it in the configured log format
+fn print_exchange(args: &Args, exchange: &Exchange) {
+    let Exchange {
+        peer: peer_info,
+        method,
+        uri,
+        http_version,
+        req_headers,
+        req_body,
+        status,
+        resp_headers,
+        resp_body,
+        error,
+        ..
+    } = exchange;
+    let time = exchange.time.format("%Y-%m-%d %H:%M:%S").to_string();
 
     let connect_line = format!(
         "{time} {peer_info} {method} {uri} {http}/{version}",
@@ -115,7 +236,14 @@ async fn print_request_response(
         http = "HTTP".blue(),
         version = http_version.blue(),
     );
-    if args.verbose >= 1 {
+    if args.log_format != LogFormat::Pretty {
+        if !args.quiet {
+            println!(
+                "{}",
+                logging::format_line(exchange, args.log_format, args.verbose)
+            );
+        }
+    } else if args.verbose >= 1 {
         let method_path_version_line = format!(
             "{method} {uri} {http}/{version}",
             method = method.green(),
@@ -124,7 +252,7 @@ async fn print_request_response(
             version = http_version.blue(),
         );
         let mut incoming_headers_vec = vec![];
-        for (hk, hv) in &req_headers {
+        for (hk, hv) in *req_headers {
             incoming_headers_vec.push(format!(
                 "{deco} {key}: {value}",
                 deco = "│".green().bold(),
@@ -138,43 +266,10 @@ async fn print_request_response(
         }
         let incoming_headers = incoming_headers_vec.join("\n");
 
-        let body = String::from_utf8_lossy(&bytes2);
-        let req_body_text = if body.is_empty() || args.verbose < 2 {
+        let req_body_text = if req_body.bytes.is_empty() || args.verbose < 2 {
             "".to_string()
         } else {
-            let mut body_invalid_json = false;
-            let body_formatted = if let Some(content_type) = req_headers.get(CONTENT_TYPE) {
-                if content_type == "application/json" {
-                    serde_json::from_str::<serde_json::Value>(&body)
-                        .and_then(|loaded_json| serde_json::to_string_pretty(&loaded_json))
-                        .and_then(|pretty_json| pretty_json.to_colored_json_auto())
-                        .unwrap_or_else(|_| {
-                            body_invalid_json = true;
-                            body.to_string()
-                        })
-                } else {
-                    body.to_string()
-                }
-            } else {
-                body.to_string()
-            };
-
-            let body_title = if body_invalid_json {
-                "Body (invalid JSON):"
-            } else {
-                "Body:"
-            };
-            let body_formatted = body_formatted
-                .lines()
-                .map(|line| format!("{deco} {line}", deco = "│".green().bold(), line = line))
-                .collect::<Vec<_>>()
-                .join("\n");
-            format!(
-                "\n{deco} {body}\n{body_formatted}",
-                deco = "│".green().bold(),
-                body = body_title.yellow(),
-                body_formatted = body_formatted,
-            )
+            pretty::format_body(req_body, req_headers, "│".green().bold())
         };
 
         let req_info = format!(
@@ -189,12 +284,12 @@ async fn print_request_response(
             "{http}/{version} {status_code} {status_text}",
             http = "HTTP".blue(),
             version = http_version.blue(),
-            status_code = resp.status().as_u16().to_string().blue(),
-            status_text = resp.status().canonical_reason().unwrap_or("").cyan(),
+            status_code = status.as_u16().to_string().blue(),
+            status_text = status.canonical_reason().unwrap_or("").cyan(),
         );
 
         let mut outgoing_headers_vec = vec![];
-        for (hk, hv) in resp.headers() {
+        for (hk, hv) in *resp_headers {
             outgoing_headers_vec.push(format!(
                 "{deco} {key}: {value}",
                 deco = "│".red().bold(),
@@ -208,21 +303,10 @@ async fn print_request_response(
         outgoing_headers_vec.sort();
         let outgoing_headers = outgoing_headers_vec.join("\n");
 
-        let resp_body_text = if args.body.is_empty() || args.verbose < 2 {
+        let resp_body_text = if resp_body.bytes.is_empty() || args.verbose < 2 {
             "".to_string()
         } else {
-            let body_formatted = args
-                .body
-                .lines()
-                .map(|line| format!("{deco} {line}", deco = "│".red().bold(), line = line))
-                .collect::<Vec<_>>()
-                .join("\n");
-            format!(
-                "\n{deco} {body}\n{body_formatted}",
-                deco = "│".red().bold(),
-                body = "Body:".yellow(),
-                body_formatted = body_formatted,
-            )
+            pretty::format_body(resp_body, resp_headers, "│".red().bold())
         };
 
         let resp_info = format!(
@@ -244,25 +328,11 @@ async fn print_request_response(
         println!("{connect_line}",);
     }
 
-    let (parts, body) = resp.into_parts();
-    let bytes = buffer_and_print("response", body).await?;
-    let resp = Response::from_parts(parts, Body::from(bytes));
-
-    Ok(resp)
-}
-
-async fn buffer_and_print(direction: &str, body: Body) -> Result<Bytes, (StatusCode, String)> {
-    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
-        Ok(bytes) => bytes,
-        Err(err) => {
-            return Err((
-                StatusCode::BAD_REQUEST,
-                format!("failed to read {direction} body: {err}"),
-            ));
+    if let (LogFormat::Pretty, Some(error)) = (args.log_format, error) {
+        if args.verbose >= 1 || !args.quiet {
+            println!("{} {error}", "Error:".red().bold());
         }
-    };
-
-    Ok(bytes)
+    }
 }
 
 #[tokio::main]
@@ -285,55 +355,87 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
-    let app = Router::new()
+    let clock = Clock::new(&args);
+    let jwt_key = match &args.jwt_key {
+        Some(path) => Some(Arc::new(SigningKey::load(path)?)),
+        None => None,
+    };
+    let state = Arc::new(AppState {
+        templates: Templates::new(&args, clock, jwt_key.clone())?,
+        args: args.clone(),
+        clock,
+        jwt_key,
+    });
+    template::spawn_reloader(state.clone())?;
+    let mut router = Router::new();
+    if let Some(jwks_path) = &args.jwks_path {
+        router = router.route(jwks_path, get(jwks));
+    }
+    let app = router
         .fallback(dummy_response)
-        .layer(middleware::from_fn(print_request_response))
-        .layer(Extension(args.clone()));
-
-    let addr = SocketAddr::from((args.interface, args.port));
-    if !args.quiet {
-        let protocol = {
-            #[cfg(feature = "tls")]
-            if args.tls_cert.is_some() {
-                "https://"
-            } else {
-                "http://"
-            }
-            #[cfg(not(feature = "tls"))]
-            "http://"
+        // Bodies are only limited by --max-body-size.
+        .layer(DefaultBodyLimit::disable())
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            reject_http_versions,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            encoding::compress_response,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            print_request_response,
+        ))
+        .with_state(state);
+
+    #[cfg(feature = "tls")]
+    let tls_config = tls::load_rustls_config(&args)?;
+    #[cfg(feature = "tls")]
+    if let Some(tls_config) = &tls_config {
+        tls::spawn_reloader(args.clone(), tls_config.clone())?;
+    }
+
+    #[cfg(feature = "tls")]
+    let listeners = listener::bind(&args, tls_config.is_some())?;
+    #[cfg(not(feature = "tls"))]
+    let listeners = listener::bind(&args, false)?;
+
+    let readiness = listener::readiness_json(&listeners);
+    if let Some(ready_file) = &args.ready_file {
+        listener::write_ready_file(ready_file, &readiness)?;
+    }
+
+    if args.print_ready_json {
+        println!("{readiness}");
+    } else if !args.quiet {
+        let addresses = listeners
+            .iter()
+            .map(|listener| listener.to_string().bold().to_string())
+            .collect::<Vec<_>>();
+        let restrictions = listener::version_restrictions(&args);
+        let restrictions = if restrictions.is_empty() {
+            "".to_string()
+        } else {
+            format!(" ({})", restrictions.join(", "))
+                .dimmed()
+                .to_string()
         };
-        println!(
+        let banner = format!(
             "{}{} {} {}{}",
             "dummyhttp v".bold(),
             crate_version!().bold(),
             "listening on".dimmed(),
-            protocol.bold(),
-            addr.to_string().bold()
+            addresses.join(", "),
+            restrictions
         );
+        logging::print_message(args.log_format, &banner);
     }
 
-    // configure certificate and private key used by https
     #[cfg(feature = "tls")]
-    if let (Some(tls_cert), Some(tls_key)) = (args.tls_cert, args.tls_key) {
-        let tls_config = RustlsConfig::from_pem_file(&tls_cert, &tls_key)
-            .await
-            .context(format!(
-                "Failed to load certificate file '{}' or key '{}'",
-                tls_cert.to_string_lossy(),
-                tls_key.to_string_lossy()
-            ))?;
-        axum_server::bind_rustls(addr, tls_config)
-            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-            .await?;
-    } else {
-        axum_server::bind(addr)
-            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-            .await?;
-    }
+    listener::serve(listeners, app, &args, tls_config).await?;
     #[cfg(not(feature = "tls"))]
-    axum_server::bind(addr)
-        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
-        .await?;
+    listener::serve(listeners, app, &args).await?;
 
     Ok(())
 }
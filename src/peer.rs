@@ -0,0 +1,126 @@
+use std::fmt;
+use std::future::Future;
+#[cfg(unix)]
+use std::future::{ready, Ready};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::http::Request;
+use axum_server::accept::Accept;
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::{unix::UCred, UnixStream};
+use tower::Service;
+
+use crate::args::ProxyProtocol;
+use crate::proxy;
+
+/// Who is on the other end of a connection
+#[derive(Debug, Clone)]
+pub enum Peer {
+    Ip(SocketAddr),
+    /// Credentials of the process connected to a Unix socket if the OS told us about them
+    #[cfg(unix)]
+    Unix(Option<UCred>),
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Ip(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            Peer::Unix(Some(cred)) => {
+                write!(f, "unix(uid={},gid={}", cred.uid(), cred.gid())?;
+                if let Some(pid) = cred.pid() {
+                    write!(f, ",pid={pid}")?;
+                }
+                write!(f, ")")
+            }
+            #[cfg(unix)]
+            Peer::Unix(None) => write!(f, "unix(unknown)"),
+        }
+    }
+}
+
+/// Acceptor attaching the `Peer` of a connection to all requests made on it
+///
+/// With PROXY protocol enabled, TCP peers are taken from the header sent by the proxy.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PeerAcceptor {
+    proxy_protocol: Option<ProxyProtocol>,
+}
+
+impl PeerAcceptor {
+    pub fn new(proxy_protocol: Option<ProxyProtocol>) -> Self {
+        Self { proxy_protocol }
+    }
+}
+
+type AcceptFuture<I, S> = Pin<Box<dyn Future<Output = io::Result<(I, WithPeer<S>)>> + Send>>;
+
+impl<S: Send + 'static> Accept<TcpStream, S> for PeerAcceptor {
+    type Stream = TcpStream;
+    type Service = WithPeer<S>;
+    type Future = AcceptFuture<TcpStream, S>;
+
+    fn accept(&self, mut stream: TcpStream, service: S) -> Self::Future {
+        let proxy_protocol = self.proxy_protocol;
+        Box::pin(async move {
+            let proxied = match proxy_protocol {
+                Some(mode) => proxy::read_header(&mut stream, mode).await?,
+                None => None,
+            };
+            let addr = match proxied {
+                Some(addr) => addr,
+                None => stream.peer_addr()?,
+            };
+            let service = WithPeer {
+                inner: service,
+                peer: Peer::Ip(addr),
+            };
+            Ok((stream, service))
+        })
+    }
+}
+
+#[cfg(unix)]
+impl<S> Accept<UnixStream, S> for PeerAcceptor {
+    type Stream = UnixStream;
+    type Service = WithPeer<S>;
+    type Future = Ready<io::Result<(Self::Stream, Self::Service)>>;
+
+    fn accept(&self, stream: UnixStream, service: S) -> Self::Future {
+        let service = WithPeer {
+            inner: service,
+            peer: Peer::Unix(stream.peer_cred().ok()),
+        };
+        ready(Ok((stream, service)))
+    }
+}
+
+/// Service inserting the `Peer` into every request's extensions
+#[derive(Debug, Clone)]
+pub struct WithPeer<S> {
+    inner: S,
+    peer: Peer,
+}
+
+impl<S, B> Service<Request<B>> for WithPeer<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        req.extensions_mut().insert(self.peer.clone());
+        self.inner.call(req)
+    }
+}
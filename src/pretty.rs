@@ -0,0 +1,219 @@
+use colored::*;
+use colored_json::ToColoredJson;
+use hyper::{header::CONTENT_TYPE, HeaderMap};
+use quick_xml::{events::Event, Reader, Writer};
+
+use crate::encoding::DisplayBody;
+
+/// Format a request or response body for the pretty log, pretty printing it if we understand its
+/// content type
+pub fn format_body(body: &DisplayBody, headers: &HeaderMap, deco: ColoredString) -> String {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let (mut notes, body_formatted) = pretty_print(&body.bytes, content_type);
+    notes.extend(body.note());
+    let body_title = if notes.is_empty() {
+        "Body:".to_string()
+    } else {
+        format!("Body ({}):", notes.join(", "))
+    };
+
+    let body_formatted = body_formatted
+        .lines()
+        .map(|line| format!("{deco} {line}", deco = deco, line = line))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "\n{deco} {body}\n{body_formatted}",
+        deco = deco,
+        body = body_title.yellow(),
+        body_formatted = body_formatted,
+    )
+}
+
+/// Pretty print `bytes` according to `content_type`, returning notes for the title and the
+/// formatted body
+///
+/// Bodies which can't be parsed as what they claim to be are printed as they are and marked as
+/// invalid in the notes.
+fn pretty_print(bytes: &[u8], content_type: &str) -> (Vec<String>, String) {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+
+    let (kind, formatted) = if mime == "application/json" || mime.ends_with("+json") {
+        ("JSON", json(bytes))
+    } else if [
+        "application/x-ndjson",
+        "application/jsonl",
+        "application/x-jsonlines",
+    ]
+    .contains(&mime.as_str())
+    {
+        ("NDJSON", ndjson(bytes))
+    } else if ["application/xml", "text/xml"].contains(&mime.as_str()) || mime.ends_with("+xml") {
+        ("XML", xml(bytes))
+    } else if mime == "application/x-www-form-urlencoded" {
+        ("form", Some(form(bytes)))
+    } else if mime == "multipart/form-data" {
+        (
+            "multipart",
+            boundary(content_type).and_then(|b| multipart(bytes, &b)),
+        )
+    } else {
+        ("", None)
+    };
+
+    match (formatted, std::str::from_utf8(bytes)) {
+        (Some(formatted), _) => (vec![], formatted),
+        (None, Ok(text)) if kind.is_empty() => (vec![], text.to_string()),
+        (None, Ok(text)) => (vec![format!("invalid {kind}")], text.to_string()),
+        (None, Err(_)) => (
+            vec![format!("{} bytes of binary data", bytes.len())],
+            hex_dump(bytes),
+        ),
+    }
+}
+
+fn json(bytes: &[u8]) -> Option<String> {
+    serde_json::from_slice::<serde_json::Value>(bytes)
+        .and_then(|loaded_json| serde_json::to_string_pretty(&loaded_json))
+        .and_then(|pretty_json| pretty_json.to_colored_json_auto())
+        .ok()
+}
+
+/// Newline delimited JSON, one compact record per line
+fn ndjson(bytes: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    let records = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line)
+                .map(|record| record.to_string())
+                .ok()
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(records.join("\n"))
+}
+
+fn xml(bytes: &[u8]) -> Option<String> {
+    let text = std::str::from_utf8(bytes).ok()?;
+    let mut reader = Reader::from_str(text);
+    reader.config_mut().trim_text(true);
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    loop {
+        match reader.read_event().ok()? {
+            Event::Eof => break,
+            event => writer.write_event(event).ok()?,
+        }
+    }
+    String::from_utf8(writer.into_inner()).ok()
+}
+
+/// URL encoded form fields as a table of decoded keys and values
+fn form(bytes: &[u8]) -> String {
+    let fields = form_urlencoded::parse(bytes).collect::<Vec<_>>();
+    let width = fields.iter().map(|(key, _)| key.chars().count()).max();
+    fields
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{key} = {value}",
+                key = format!("{key:width$}", width = width.unwrap_or(0)).cyan(),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Extract the boundary parameter from a multipart content type
+fn boundary(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("boundary") {
+            return None;
+        }
+        Some(value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// Split a multipart body into its parts, printing their headers followed by either their text
+/// or, for files and binary data, their size
+fn multipart(bytes: &[u8], boundary: &str) -> Option<String> {
+    let delimiter = format!("--{boundary}");
+    let delimiter = delimiter.as_bytes();
+
+    let mut parts = vec![];
+    let mut rest = &bytes[find(bytes, delimiter)? + delimiter.len()..];
+    while !rest.starts_with(b"--") {
+        rest = rest.strip_prefix(b"\r\n")?;
+        let end = find(rest, delimiter)?;
+        // The CRLF before the delimiter belongs to the delimiter.
+        let part = rest[..end].strip_suffix(b"\r\n")?;
+        rest = &rest[end + delimiter.len()..];
+
+        let (head, content) = match part.strip_prefix(b"\r\n") {
+            Some(content) => (&[][..], content),
+            None => {
+                let header_end = find(part, b"\r\n\r\n")?;
+                (&part[..header_end], &part[header_end + 4..])
+            }
+        };
+        let head = std::str::from_utf8(head).ok()?;
+        let is_file = head.lines().any(|line| {
+            line.to_lowercase().starts_with("content-disposition:") && line.contains("filename=")
+        });
+
+        let mut lines = vec![format!("Part {}:", parts.len() + 1).yellow().to_string()];
+        for line in head.lines() {
+            let (name, value) = line.split_once(':')?;
+            lines.push(format!("  {}:{}", name.cyan(), value));
+        }
+        match std::str::from_utf8(content) {
+            Ok(text) if !is_file => lines.extend(text.lines().map(|line| format!("  {line}"))),
+            _ => lines.push(format!("  <{} bytes>", content.len()).dimmed().to_string()),
+        }
+        parts.push(lines.join("\n"));
+    }
+
+    Some(parts.join("\n"))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Classic hex dump with offsets, 16 bytes per line and printable ASCII on the right
+fn hex_dump(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let hex = chunk
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let ascii = chunk
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect::<String>();
+            format!("{:08x}  {hex:<47}  |{ascii}|", i * 16)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,121 @@
+use std::io;
+use std::net::SocketAddr;
+
+use ppp::{v1, v2};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+use tokio::time::{sleep, timeout, Duration};
+
+use crate::args::ProxyProtocol;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_FIXED_LENGTH: usize = 16;
+
+/// How long a connection may take to send a whole header, or to show that it doesn't start with one
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Version {
+    V1,
+    V2,
+}
+
+fn invalid(msg: impl ToString) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid PROXY protocol header: {}", msg.to_string()),
+    )
+}
+
+/// Find out whether the connection starts with a PROXY protocol header without consuming anything
+async fn detect(stream: &TcpStream) -> io::Result<Option<Version>> {
+    let mut buf = [0; V2_SIGNATURE.len()];
+    loop {
+        let n = stream.peek(&mut buf).await?;
+        let peeked = &buf[..n];
+        if n == 0 {
+            return Ok(None);
+        } else if peeked.starts_with(V1_PREFIX) {
+            return Ok(Some(Version::V1));
+        } else if peeked.starts_with(V2_SIGNATURE) {
+            return Ok(Some(Version::V2));
+        } else if !V1_PREFIX.starts_with(peeked) && !V2_SIGNATURE.starts_with(peeked) {
+            return Ok(None);
+        }
+        // Only part of a signature has arrived so far, peeking again right away would spin.
+        sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// Consume a v1 header, returning the client address it carries
+async fn read_v1(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    // Read byte by byte so we never consume any of the request following the header.
+    let mut header = Vec::with_capacity(V1_MAX_LENGTH);
+    while !header.ends_with(b"\r\n") {
+        if header.len() >= V1_MAX_LENGTH {
+            return Err(invalid("v1 header too long"));
+        }
+        header.push(stream.read_u8().await?);
+    }
+
+    let header = v1::Header::try_from(header.as_slice()).map_err(invalid)?;
+    Ok(match header.addresses {
+        v1::Addresses::Tcp4(addr) => {
+            Some(SocketAddr::from((addr.source_address, addr.source_port)))
+        }
+        v1::Addresses::Tcp6(addr) => {
+            Some(SocketAddr::from((addr.source_address, addr.source_port)))
+        }
+        v1::Addresses::Unknown => None,
+    })
+}
+
+/// Consume a v2 header, returning the client address it carries
+async fn read_v2(stream: &mut TcpStream) -> io::Result<Option<SocketAddr>> {
+    let mut header = vec![0; V2_FIXED_LENGTH];
+    stream.read_exact(&mut header).await?;
+    let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+    header.resize(V2_FIXED_LENGTH + length, 0);
+    stream.read_exact(&mut header[V2_FIXED_LENGTH..]).await?;
+
+    let header = v2::Header::try_from(header.as_slice()).map_err(invalid)?;
+    if header.command == v2::Command::Local {
+        // Sent by the proxy itself, e.g. for health checks.
+        return Ok(None);
+    }
+    Ok(match header.addresses {
+        v2::Addresses::IPv4(addr) => {
+            Some(SocketAddr::from((addr.source_address, addr.source_port)))
+        }
+        v2::Addresses::IPv6(addr) => {
+            Some(SocketAddr::from((addr.source_address, addr.source_port)))
+        }
+        v2::Addresses::Unix(_) | v2::Addresses::Unspecified => None,
+    })
+}
+
+/// Consume the PROXY protocol header at the start of `stream`
+///
+/// Returns the original client address if the header carried one. Connections without a header
+/// are an error in `ProxyProtocol::Required` mode and are passed through untouched otherwise.
+pub async fn read_header(
+    stream: &mut TcpStream,
+    mode: ProxyProtocol,
+) -> io::Result<Option<SocketAddr>> {
+    let header = async {
+        let version = match (detect(stream).await?, mode) {
+            (Some(version), _) => version,
+            (None, ProxyProtocol::Optional) => return Ok(None),
+            (None, ProxyProtocol::Required) => return Err(invalid("missing")),
+        };
+        match version {
+            Version::V1 => read_v1(stream).await,
+            Version::V2 => read_v2(stream).await,
+        }
+    };
+    timeout(HEADER_TIMEOUT, header)
+        .await
+        .map_err(|_| invalid("timed out"))?
+}
@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use anyhow::{anyhow, Context as _, Result};
+use axum::{
+    http::{header::CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use colored::*;
+use fake::rand::Rng;
+use tera::Tera;
+
+use crate::args::{parse_status, Args};
+use crate::clock::Clock;
+use crate::faker::{self, RequestRngs};
+use crate::filters;
+use crate::jwt::{self, SigningKey};
+use crate::logging;
+use crate::peer::Peer;
+use crate::watch;
+use crate::AppState;
+
+/// Name under which the body given on the command line is registered
+const BODY_TEMPLATE: &str = "body";
+/// Name under which the status code given on the command line is registered
+const STATUS_TEMPLATE: &str = "status";
+
+pub fn template_uuid(
+    _kwargs: tera::Kwargs,
+    _state: &tera::State<'_>,
+) -> tera::TeraResult<tera::Value> {
+    let bytes = faker::with_rng(|rng| rng.random());
+    let uuid = uuid::Builder::from_random_bytes(bytes).into_uuid();
+    Ok(tera::Value::from(uuid.to_string()))
+}
+
+pub fn template_lorem(
+    kwargs: tera::Kwargs,
+    _state: &tera::State<'_>,
+) -> tera::TeraResult<tera::Value> {
+    let n_words = kwargs.get::<u64>("words")?.ok_or_else(|| {
+        tera::Error::message("lorem() needs the number of words, e.g. lorem(words=10)")
+    })?;
+    Ok(tera::Value::from(lipsum::lipsum(n_words as usize)))
+}
+
+pub fn template_env(
+    kwargs: tera::Kwargs,
+    _state: &tera::State<'_>,
+) -> tera::TeraResult<tera::Value> {
+    let name = kwargs.must_get::<&str>("name")?;
+    match std::env::var(name) {
+        Ok(value) => Ok(tera::Value::from(value)),
+        Err(_) => kwargs.get::<tera::Value>("default")?.ok_or_else(|| {
+            tera::Error::message(format!("env() found no variable {name} and got no default"))
+        }),
+    }
+}
+
+pub fn template_file(
+    kwargs: tera::Kwargs,
+    _state: &tera::State<'_>,
+) -> tera::TeraResult<tera::Value> {
+    let path = kwargs.must_get::<&str>("path")?;
+    let contents = fs::read_to_string(path)
+        .map_err(|err| tera::Error::message(format!("file() failed to read {path}: {err}")))?;
+    Ok(tera::Value::from(contents))
+}
+
+/// Current values of the counters handed out by `counter()`
+///
+/// Counters are told apart by their name and, if counting per path, the request path.
+#[derive(Default)]
+struct Counters(Mutex<HashMap<(String, Option<String>), u64>>);
+
+impl Counters {
+    fn next(&self, kwargs: &tera::Kwargs, state: &tera::State<'_>) -> tera::TeraResult<u64> {
+        let name = kwargs.get::<&str>("name")?.unwrap_or_default().to_string();
+        let path = if kwargs.get::<bool>("per_path")?.unwrap_or(false) {
+            let request = state.get::<tera::Value>("request")?;
+            let path = request
+                .as_ref()
+                .and_then(|request| request.get_from_path("path"))
+                .and_then(|path| path.as_str());
+            Some(path.unwrap_or_default().to_string())
+        } else {
+            None
+        };
+        let mut counters = self.0.lock().unwrap();
+        let count = counters.entry((name, path)).or_default();
+        *count += 1;
+        Ok(*count)
+    }
+}
+
+/// Whether `text` contains anything Tera would interpret
+pub fn has_template_syntax(text: &str) -> bool {
+    ["{{", "{%", "{#"].iter().any(|start| text.contains(start))
+}
+
+/// Why rendering a template failed
+///
+/// Attached to the error response so that the log can explain it as well.
+#[derive(Debug, Clone)]
+pub struct RenderError(pub String);
+
+impl RenderError {
+    /// `what` names the template that failed, e.g. "the body template"
+    fn new(what: &str, err: &tera::Error) -> Self {
+        let err_text = err.to_string();
+        let mut message = format!(
+            "Failed to render {what}: {}",
+            err_text.strip_prefix("error: ").unwrap_or(&err_text)
+        );
+        let mut source = std::error::Error::source(err);
+        while let Some(err) = source {
+            message.push_str(&format!(": {err}"));
+            source = err.source();
+        }
+        Self(message)
+    }
+
+    /// Plain text response explaining the error
+    pub fn into_response(self, status: StatusCode) -> Response {
+        let mut resp = (
+            status,
+            [(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/plain; charset=utf-8"),
+            )],
+            format!("{}\n", self.0),
+        )
+            .into_response();
+        resp.extensions_mut().insert(self);
+        resp
+    }
+}
+
+/// Part of the response that is either fixed or rendered for every request
+enum Part<T> {
+    Static(T),
+    /// Name of the template to render
+    Template(String),
+}
+
+/// The request being answered, which templates can refer to as `request`
+pub struct RequestInfo<'a> {
+    /// The client, as told by the PROXY protocol header if there is one
+    pub peer: &'a Peer,
+    pub method: &'a Method,
+    pub uri: &'a Uri,
+    pub headers: &'a HeaderMap,
+    /// The beginning of the body, up to --template-body-limit
+    pub body: &'a [u8],
+}
+
+impl RequestInfo<'_> {
+    fn context(&self) -> tera::Context {
+        // Parameters and headers given more than once are represented by their first value.
+        let mut query = serde_json::Map::new();
+        let params = form_urlencoded::parse(self.uri.query().unwrap_or_default().as_bytes());
+        for (name, value) in params {
+            query.entry(name).or_insert_with(|| value.into());
+        }
+        let mut headers = serde_json::Map::new();
+        for (name, value) in self.headers {
+            headers
+                .entry(name.as_str())
+                .or_insert_with(|| String::from_utf8_lossy(value.as_bytes()).into());
+        }
+        let mut context = tera::Context::new();
+        context.insert(
+            "request",
+            &serde_json::json!({
+                "client": self.peer.to_string(),
+                "client_ip": match self.peer {
+                    Peer::Ip(addr) => Some(addr.ip().to_string()),
+                    #[cfg(unix)]
+                    Peer::Unix(_) => None,
+                },
+                "method": self.method.as_str(),
+                "path": self.uri.path(),
+                "query": query,
+                "headers": headers,
+                "body": String::from_utf8_lossy(self.body),
+            }),
+        );
+        context
+    }
+}
+
+/// A response rendered from the templates
+pub struct Rendered {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// The response templates, compiled once at startup
+pub struct Templates {
+    /// Replaced whenever the files in --templates-dir change
+    tera: RwLock<Tera>,
+    status: Part<StatusCode>,
+    headers: Vec<(HeaderName, Part<HeaderValue>)>,
+    body: Part<Bytes>,
+    /// Whether any template refers to `request` and so might need the request body
+    uses_request: AtomicBool,
+    rngs: RequestRngs,
+}
+
+impl Templates {
+    pub fn new(args: &Args, clock: Clock, jwt_key: Option<Arc<SigningKey>>) -> Result<Self> {
+        let mut tera = Tera::default();
+        tera.register_function("now", move |_: tera::Kwargs, _: &tera::State<'_>| {
+            clock.now().to_utc().to_rfc3339()
+        });
+        tera.register_function("uuid", template_uuid);
+        tera.register_function("lorem", template_lorem);
+        tera.register_function("env", template_env);
+        tera.register_function("file", template_file);
+        let counters = Counters::default();
+        tera.register_function(
+            "counter",
+            move |kwargs: tera::Kwargs, state: &tera::State<'_>| counters.next(&kwargs, state),
+        );
+        faker::register_functions(&mut tera);
+        filters::register_filters(&mut tera);
+        jwt::register_function(&mut tera, jwt_key);
+
+        if let Some(dir) = &args.templates_dir {
+            if !dir.is_dir() {
+                return Err(anyhow!("Templates directory {} not found", dir.display()));
+            }
+            tera.load_from_glob(&format!("{}/**/*", dir.display()))
+                .with_context(|| format!("Failed to load the templates in {}", dir.display()))?;
+        }
+
+        let status = if has_template_syntax(&args.code) {
+            tera.add_raw_template(STATUS_TEMPLATE, &args.code)
+                .context("Failed to parse the status code template")?;
+            Part::Template(STATUS_TEMPLATE.to_string())
+        } else {
+            Part::Static(parse_status(&args.code).map_err(|err| anyhow!(err))?)
+        };
+
+        let mut headers = Vec::new();
+        for (name, value) in args.headers.iter().flat_map(|header| header.iter()) {
+            // Header values have to be visible ASCII so they're always valid strings.
+            let text = value.to_str().unwrap_or_default();
+            if !has_template_syntax(text) {
+                headers.push((name.clone(), Part::Static(value.clone())));
+                continue;
+            }
+            let template = format!("header {name}");
+            tera.add_raw_template(&template, text)
+                .with_context(|| format!("Failed to parse the template for header {name}"))?;
+            headers.push((name.clone(), Part::Template(template)));
+        }
+
+        let body = match (&args.body_file, &args.body_template) {
+            (Some(path), _) => fs::read_to_string(path).with_context(|| {
+                format!("Failed to read the body template from {}", path.display())
+            })?,
+            (_, Some(name)) => {
+                if !tera.get_template_names().any(|template| template == name) {
+                    return Err(anyhow!("Body template {name} not found in --templates-dir"));
+                }
+                String::new()
+            }
+            (None, None) => args.body.clone(),
+        };
+        let body = if let Some(name) = &args.body_template {
+            // Even without template syntax now, the file might get some later on.
+            Part::Template(name.clone())
+        } else if has_template_syntax(&body) {
+            tera.add_raw_template(BODY_TEMPLATE, &body)
+                .context("Failed to parse the body template")?;
+            Part::Template(BODY_TEMPLATE.to_string())
+        } else {
+            Part::Static(Bytes::from(body))
+        };
+
+        Ok(Self {
+            uses_request: AtomicBool::new(refers_to_request(&tera)),
+            tera: RwLock::new(tera),
+            status,
+            headers,
+            body,
+            rngs: RequestRngs::new(args.seed),
+        })
+    }
+
+    /// Reload the templates in --templates-dir, keeping the old ones if that fails
+    fn reload(&self) -> Result<()> {
+        let mut tera = self.tera.read().unwrap().clone();
+        tera.full_reload()?;
+        if let Part::Template(name) = &self.body {
+            if !tera.get_template_names().any(|template| template == name) {
+                return Err(anyhow!("Body template {name} not found"));
+            }
+        }
+        self.uses_request
+            .store(refers_to_request(&tera), Ordering::Relaxed);
+        *self.tera.write().unwrap() = tera;
+        Ok(())
+    }
+
+    /// Whether the request body has to be kept around for rendering
+    pub fn uses_request(&self) -> bool {
+        self.uses_request.load(Ordering::Relaxed)
+    }
+
+    /// Whether any part of the response has to be rendered
+    fn is_static(&self) -> bool {
+        matches!(self.status, Part::Static(_))
+            && matches!(self.body, Part::Static(_))
+            && self
+                .headers
+                .iter()
+                .all(|(_, value)| matches!(value, Part::Static(_)))
+    }
+
+    /// Render the status code, headers and body answering `request`
+    pub fn render(&self, request: &RequestInfo<'_>) -> Result<Rendered, RenderError> {
+        let tera = self.tera.read().unwrap();
+        if self.is_static() {
+            return self.render_parts(&tera, tera::Context::new());
+        }
+        let context = request.context();
+        self.rngs.with_next(|| self.render_parts(&tera, context))
+    }
+
+    fn render_parts(
+        &self,
+        tera: &Tera,
+        mut context: tera::Context,
+    ) -> Result<Rendered, RenderError> {
+        // The body goes first so that the status code and headers can refer to it, e.g. to sign it.
+        let body = match &self.body {
+            Part::Static(body) => body.clone(),
+            Part::Template(template) => tera
+                .render(template, &context)
+                .map(Bytes::from)
+                .map_err(|err| RenderError::new("the body template", &err))?,
+        };
+        let templated_headers = self
+            .headers
+            .iter()
+            .any(|(_, value)| matches!(value, Part::Template(_)));
+        if matches!(self.status, Part::Template(_)) || templated_headers {
+            context.insert("body", &String::from_utf8_lossy(&body));
+        }
+
+        let status = match &self.status {
+            Part::Static(status) => *status,
+            Part::Template(template) => {
+                let rendered = tera
+                    .render(template, &context)
+                    .map_err(|err| RenderError::new("the status code template", &err))?;
+                parse_status(&rendered).map_err(|err| {
+                    RenderError(format!("Failed to render the status code template: {err}"))
+                })?
+            }
+        };
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let value = match value {
+                Part::Static(value) => value.clone(),
+                Part::Template(template) => {
+                    let what = format!("the template for header {name}");
+                    let rendered = tera
+                        .render(template, &context)
+                        .map_err(|err| RenderError::new(&what, &err))?;
+                    HeaderValue::from_str(&rendered).map_err(|_| {
+                        RenderError(format!(
+                            "Failed to render {what}: invalid value {rendered:?}"
+                        ))
+                    })?
+                }
+            };
+            headers.insert(name.clone(), value);
+        }
+
+        Ok(Rendered {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// Whether any of the templates refers to `request`
+fn refers_to_request(tera: &Tera) -> bool {
+    tera.get_template_names().any(|name| {
+        tera.get_template_variables(name)
+            .is_ok_and(|variables| variables.contains("request"))
+    })
+}
+
+/// Reload the templates whenever the files in --templates-dir change
+pub fn spawn_reloader(state: Arc<AppState>) -> Result<()> {
+    let Some(dir) = state.args.templates_dir.clone() else {
+        return Ok(());
+    };
+    let dir = dir
+        .canonicalize()
+        .with_context(|| format!("Failed to watch '{}'", dir.to_string_lossy()))?;
+
+    watch::spawn_reloader(vec![dir], move || {
+        let result = state.templates.reload();
+        if state.args.quiet {
+            return;
+        }
+        let time = state.clock.now().format("%Y-%m-%d %H:%M:%S").to_string();
+        let message = match result {
+            Ok(()) => format!("{} {}", time.yellow(), "Reloaded templates".green().bold()),
+            Err(e) => format!(
+                "{} {} {e:#}",
+                time.yellow(),
+                "Failed to reload templates, keeping the old ones:"
+                    .red()
+                    .bold(),
+            ),
+        };
+        logging::print_message(state.args.log_format, &message);
+    })
+    .context("Failed to watch the templates")?;
+
+    Ok(())
+}
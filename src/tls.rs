@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Context, Result};
+use axum_server::tls_rustls::RustlsConfig;
+use chrono::DateTime;
+use colored::*;
+use rustls::{
+    crypto::CryptoProvider,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::args::{Args, UnknownSni};
+use crate::clock::Clock;
+use crate::logging;
+use crate::watch;
+
+/// Picks the certificate to present based on the SNI hostname sent by the client
+#[derive(Debug)]
+struct SniResolver {
+    /// Served if the client didn't send a hostname or (depending on `unknown_sni`) if we don't
+    /// know the hostname
+    default: Option<Arc<CertifiedKey>>,
+    by_hostname: HashMap<String, Arc<CertifiedKey>>,
+    unknown_sni: UnknownSni,
+}
+
+impl SniResolver {
+    /// Find a certificate for `hostname`, either exactly or via a wildcard entry like
+    /// `*.example.com`
+    fn lookup(&self, hostname: &str) -> Option<&Arc<CertifiedKey>> {
+        let hostname = hostname.to_lowercase();
+        self.by_hostname.get(&hostname).or_else(|| {
+            let (_, parent) = hostname.split_once('.')?;
+            self.by_hostname.get(&format!("*.{parent}"))
+        })
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let Some(hostname) = client_hello.server_name() else {
+            return self.default.clone();
+        };
+        match (self.lookup(hostname), self.unknown_sni) {
+            (Some(certified_key), _) => Some(certified_key.clone()),
+            (None, UnknownSni::Fallback) => self.default.clone(),
+            (None, UnknownSni::Reject) => None,
+        }
+    }
+}
+
+/// Load a certificate chain and its private key from PEM files
+fn load_certified_key(cert: &Path, key: &Path, provider: &CryptoProvider) -> Result<CertifiedKey> {
+    let context = || {
+        format!(
+            "Failed to load certificate file '{}' or key '{}'",
+            cert.to_string_lossy(),
+            key.to_string_lossy()
+        )
+    };
+
+    let cert_chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(context)?;
+    if cert_chain.is_empty() {
+        return Err(anyhow!("The certificate file contained no certificates"))
+            .with_context(context);
+    }
+    let key = PrivateKeyDer::from_pem_file(key).with_context(context)?;
+
+    CertifiedKey::from_der(cert_chain, key, provider).with_context(context)
+}
+
+/// Load all certificates given on the command line into a resolver
+fn load_resolver(args: &Args, provider: &CryptoProvider) -> Result<SniResolver> {
+    let mut default = None;
+    let mut by_hostname = HashMap::new();
+    for tls_cert in &args.tls_cert {
+        match (&tls_cert.hostname, &tls_cert.key) {
+            (Some(hostname), Some(key)) => {
+                let certified_key = load_certified_key(&tls_cert.cert, key, provider)?;
+                by_hostname.insert(hostname.clone(), Arc::new(certified_key));
+            }
+            (None, _) => {
+                if default.is_some() {
+                    bail!("Only one --tls-cert may be given without a hostname");
+                }
+                let Some(key) = &args.tls_key else {
+                    bail!("--tls-cert without a hostname requires --tls-key");
+                };
+                let certified_key = load_certified_key(&tls_cert.cert, key, provider)?;
+                default = Some(Arc::new(certified_key));
+            }
+            (Some(_), None) => unreachable!("hostname certificates always come with a key"),
+        }
+    }
+    if default.is_none() && args.tls_key.is_some() {
+        bail!("--tls-key requires a --tls-cert without a hostname");
+    }
+
+    // Without an explicit default, fall back to the first hostname certificate so clients not
+    // sending SNI still get something.
+    if default.is_none() {
+        let first_hostname = args.tls_cert.iter().find_map(|c| c.hostname.as_ref());
+        default = first_hostname.and_then(|h| by_hostname.get(h).cloned());
+    }
+
+    Ok(SniResolver {
+        default,
+        by_hostname,
+        unknown_sni: args.tls_unknown_sni,
+    })
+}
+
+/// Build a rustls `ServerConfig` from the certificates given on the command line
+fn load_server_config(args: &Args) -> Result<(ServerConfig, Arc<SniResolver>)> {
+    let builder = ServerConfig::builder().with_no_client_auth();
+    let resolver = Arc::new(load_resolver(args, builder.crypto_provider())?);
+
+    let mut config = builder.with_cert_resolver(resolver.clone());
+    config.alpn_protocols = if args.http1_only {
+        vec![b"http/1.1".to_vec()]
+    } else if args.http2_only {
+        vec![b"h2".to_vec()]
+    } else {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    };
+
+    Ok((config, resolver))
+}
+
+/// Build the TLS configuration from the certificates given on the command line
+///
+/// Returns `None` if no certificates were given, i.e. we should serve plain HTTP.
+pub fn load_rustls_config(args: &Args) -> Result<Option<RustlsConfig>> {
+    if args.tls_cert.is_empty() {
+        return Ok(None);
+    }
+
+    let (config, _) = load_server_config(args)?;
+    Ok(Some(RustlsConfig::from_config(Arc::new(config))))
+}
+
+/// Describe a certificate by its subject and expiry date
+fn describe_certificate(certified_key: &CertifiedKey) -> String {
+    let Ok(end_entity) = certified_key.end_entity_cert() else {
+        return "<empty certificate chain>".to_string();
+    };
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(end_entity) else {
+        return "<unparsable certificate>".to_string();
+    };
+    let not_after = DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+        .map(|expiry| expiry.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    format!("{} (expires {})", cert.subject(), not_after)
+}
+
+/// Describe which certificates are now being served after a reload
+fn describe_reloaded(resolver: &SniResolver, clock: Clock) -> String {
+    let time = clock.now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let mut lines = vec![format!(
+        "{} {}",
+        time.yellow(),
+        "Reloaded TLS certificates".green().bold()
+    )];
+
+    if let Some(default) = &resolver.default {
+        lines.push(format!(
+            "{deco} {name}: {cert}",
+            deco = "│".green().bold(),
+            name = "default".cyan(),
+            cert = describe_certificate(default)
+        ));
+    }
+    let mut hostnames = resolver.by_hostname.keys().collect::<Vec<_>>();
+    hostnames.sort();
+    for hostname in hostnames {
+        lines.push(format!(
+            "{deco} {name}: {cert}",
+            deco = "│".green().bold(),
+            name = hostname.cyan(),
+            cert = describe_certificate(&resolver.by_hostname[hostname])
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Reload the certificates into the live `tls_config`, keeping the old ones if that fails
+fn reload(args: &Args, tls_config: &RustlsConfig) {
+    match load_server_config(args) {
+        Ok((config, resolver)) => {
+            tls_config.reload_from_config(Arc::new(config));
+            if !args.quiet {
+                let message = describe_reloaded(&resolver, Clock::new(args));
+                logging::print_message(args.log_format, &message);
+            }
+        }
+        Err(e) => {
+            if !args.quiet {
+                let time = Clock::new(args)
+                    .now()
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string();
+                let message = format!(
+                    "{} {} {e:#}",
+                    time.yellow(),
+                    "Failed to reload TLS certificates, keeping the old ones:"
+                        .red()
+                        .bold(),
+                );
+                logging::print_message(args.log_format, &message);
+            }
+        }
+    }
+}
+
+/// All certificate and key files, made absolute so we can match them against watcher events
+fn watched_files(args: &Args) -> Vec<PathBuf> {
+    let paths = args
+        .tls_cert
+        .iter()
+        .flat_map(|tls_cert| [Some(&tls_cert.cert), tls_cert.key.as_ref()])
+        .chain([args.tls_key.as_ref()])
+        .flatten();
+
+    paths
+        .filter_map(|path| {
+            let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+            let parent = parent.unwrap_or(Path::new(".")).canonicalize().ok()?;
+            Some(parent.join(path.file_name()?))
+        })
+        .collect()
+}
+
+/// Reload the certificates whenever their files change or we receive SIGHUP
+pub fn spawn_reloader(args: Args, tls_config: RustlsConfig) -> Result<()> {
+    let files = watched_files(&args);
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    let trigger = watch::spawn_reloader(files, move || reload(&args, &tls_config))
+        .context("Failed to watch TLS certificate files")?;
+
+    #[cfg(unix)]
+    {
+        let mut hangup = signal(SignalKind::hangup()).context("Failed to listen for SIGHUP")?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                let _ = trigger.send(());
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// Resolver handing out whatever certificates the TCP listeners currently use
+///
+/// This way QUIC picks up reloaded certificates as well.
+#[cfg(feature = "http3")]
+#[derive(Debug)]
+struct LiveResolver(RustlsConfig);
+
+#[cfg(feature = "http3")]
+impl ResolvesServerCert for LiveResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.0.get_inner().cert_resolver.resolve(client_hello)
+    }
+}
+
+/// Build the QUIC configuration sharing its certificates with `tls_config`
+#[cfg(feature = "http3")]
+pub fn quic_server_config(tls_config: &RustlsConfig) -> Result<quinn::ServerConfig> {
+    let mut config = ServerConfig::builder_with_protocol_versions(&[&rustls::version::TLS13])
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(LiveResolver(tls_config.clone())));
+    config.alpn_protocols = vec![b"h3".to_vec()];
+
+    let config = quinn::crypto::rustls::QuicServerConfig::try_from(config)
+        .context("Failed to set up TLS for QUIC")?;
+    Ok(quinn::ServerConfig::with_crypto(Arc::new(config)))
+}
@@ -0,0 +1,68 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use notify::{RecursiveMode, Watcher};
+use tokio::{
+    sync::mpsc,
+    time::{sleep, Duration},
+};
+
+/// Call `reload` whenever any of `paths` changes
+///
+/// Directories are watched along with everything below them. Files are watched through the
+/// directory containing them as they're commonly replaced by moving new ones into place. Paths
+/// have to be absolute to match the ones reported by the watcher.
+///
+/// Sending to the returned channel triggers a reload as well, e.g. on SIGHUP.
+pub fn spawn_reloader(
+    paths: Vec<PathBuf>,
+    mut reload: impl FnMut() + Send + 'static,
+) -> Result<mpsc::UnboundedSender<()>> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    let (dirs, files): (Vec<_>, Vec<_>) = paths.into_iter().partition(|path| path.is_dir());
+    let watcher_dirs = dirs.clone();
+    let watcher_files = files.clone();
+    let watcher_tx = tx.clone();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let relevant = event.paths.iter().any(|path| {
+            watcher_files.contains(path) || watcher_dirs.iter().any(|dir| path.starts_with(dir))
+        });
+        if !event.kind.is_access() && relevant {
+            let _ = watcher_tx.send(());
+        }
+    })
+    .context("Failed to watch for changes")?;
+    let watches = dirs
+        .iter()
+        .map(|dir| (dir.as_path(), RecursiveMode::Recursive))
+        .chain(
+            files
+                .iter()
+                .filter_map(|file| file.parent())
+                .map(|dir| (dir, RecursiveMode::NonRecursive)),
+        );
+    for (dir, mode) in watches {
+        watcher
+            .watch(dir, mode)
+            .with_context(|| format!("Failed to watch '{}'", dir.to_string_lossy()))?;
+    }
+
+    tokio::spawn(async move {
+        // Moving the watcher in here keeps it alive for as long as we're reloading.
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            // Files tend to be replaced in several steps, like editors saving or certificate and
+            // key being rotated one after the other, so wait for things to settle.
+            sleep(Duration::from_millis(200)).await;
+            while rx.try_recv().is_ok() {}
+
+            reload();
+        }
+    });
+
+    Ok(tx)
+}
@@ -4,8 +4,8 @@ use assert_cmd::prelude::*;
 use clap::{crate_name, crate_version};
 use reqwest::blocking::Client;
 use rstest::rstest;
-use std::io::Read;
-use std::process::Command;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
 use utils::{DummyhttpProcess, Error};
 
 /// Show help and exit.
@@ -128,3 +128,265 @@ fn has_very_verbose_output() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// With -vv, the response body is logged as it was rendered and pretty printed by content type.
+#[test]
+fn logs_rendered_response_body() -> Result<(), Error> {
+    let mut dh = DummyhttpProcess::new(vec![
+        "-vv",
+        "-H",
+        "content-type:application/json",
+        "-b",
+        r#"{"answer":{{ 40 + 2 }}}"#,
+    ])?;
+
+    Client::new().get(&dh.url).send()?.error_for_status()?;
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    assert!(output.contains(r#""answer": 42"#));
+    assert!(!output.contains("{{"));
+
+    Ok(())
+}
+
+/// Templates with syntax errors are refused at startup, pointing at the error.
+#[test]
+fn rejects_invalid_template() -> Result<(), Error> {
+    Command::cargo_bin("dummyhttp")?
+        .args(["-b", "{{ foo("])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "Failed to parse the body template",
+        ))
+        .stderr(predicates::str::contains("body:1:8"));
+
+    Ok(())
+}
+
+/// Templates failing to render are logged with the reason.
+#[test]
+fn logs_template_errors() -> Result<(), Error> {
+    let mut dh = DummyhttpProcess::new(vec!["-b", "{{ lorem() }}"])?;
+
+    Client::new().get(&dh.url).send()?;
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    assert!(output.contains("Error: Failed to render the body template"));
+    assert!(output.contains("lorem() needs the number of words"));
+
+    Ok(())
+}
+
+/// With -vv, request bodies are pretty printed according to their content type.
+#[rstest(
+    content_type,
+    body,
+    expected,
+    case::json_charset(
+        "application/json; charset=utf-8",
+        &br#"{"a":[1]}"#[..],
+        "\"a\": [\n"
+    ),
+    case::problem_json("application/problem+json", &br#"{"a":1}"#[..], "\"a\": 1"),
+    case::invalid_json("application/json", &b"{"[..], "Body (invalid JSON):"),
+    case::ndjson("application/x-ndjson", &b"{\"a\": 1}\n{\"b\": 2}\n"[..], "{\"b\":2}"),
+    case::xml("application/soap+xml", &b"<a><b>c</b></a>"[..], "<a>\n│ \x20 <b>c</b>"),
+    case::form(
+        "application/x-www-form-urlencoded",
+        &b"name=J%C3%BCrgen+Doe&x=1"[..],
+        "name = Jürgen Doe"
+    ),
+    case::multipart(
+        "multipart/form-data; boundary=XyZ",
+        &b"--XyZ\r\nContent-Disposition: form-data; name=\"field\"\r\n\r\nvalue\r\n--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.bin\"\r\n\r\n12345\r\n--XyZ--\r\n"[..],
+        "filename=\"a.bin\"\n│   <5 bytes>"
+    ),
+    case::binary("application/octet-stream", &b"\xff\x00ab"[..], "00000000  ff 00 61 62")
+)]
+fn pretty_prints_request_body(
+    content_type: &str,
+    body: &'static [u8],
+    expected: &str,
+) -> Result<(), Error> {
+    let mut dh = DummyhttpProcess::new(vec!["-vv"])?;
+
+    Client::new()
+        .post(&dh.url)
+        .header("content-type", content_type)
+        .body(body)
+        .send()?
+        .error_for_status()?;
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    assert!(output.contains(expected), "{output}");
+
+    Ok(())
+}
+
+/// With -vv, compressed request bodies are shown decompressed along with both sizes.
+#[test]
+fn decompresses_request_body() -> Result<(), Error> {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let mut dh = DummyhttpProcess::new(vec!["-vv"])?;
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(br#"{"compressed":true}"#)?;
+    let compressed = encoder.finish()?;
+    let compressed_len = compressed.len();
+
+    let client = Client::new();
+    for body in [compressed, b"not gzip".to_vec()] {
+        client
+            .post(&dh.url)
+            .header("content-type", "application/json")
+            .header("content-encoding", "gzip")
+            .body(body)
+            .send()?
+            .error_for_status()?;
+    }
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    assert!(output.contains(&format!("Body (gzip, {compressed_len} → 19 bytes):")));
+    assert!(output.contains(r#""compressed": true"#));
+    assert!(output.contains("Body (invalid JSON, gzip, failed to decompress:"));
+
+    Ok(())
+}
+
+/// If we pass --print-ready-json, the first line tells us where we're listening.
+#[test]
+fn prints_ready_json() -> Result<(), Error> {
+    let mut child = Command::cargo_bin("dummyhttp")?
+        .args(["-p", "0", "-i", "127.0.0.1", "--print-ready-json"])
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let mut first_line = String::new();
+    BufReader::new(child.stdout.as_mut().unwrap()).read_line(&mut first_line)?;
+    let readiness: serde_json::Value = serde_json::from_str(&first_line)?;
+    let listener = &readiness["listeners"][0];
+    let port = listener["port"].as_u64().unwrap();
+
+    assert_eq!(readiness["pid"], child.id());
+    assert_eq!(listener["scheme"], "http");
+    assert_eq!(listener["url"], format!("http://127.0.0.1:{port}"));
+    assert_ne!(port, 0);
+
+    let resp = reqwest::blocking::get(format!("http://127.0.0.1:{port}"));
+    child.kill()?;
+    assert_eq!(resp?.text()?, "dummyhttp");
+
+    Ok(())
+}
+
+/// HTTP version restrictions are shown in the startup banner.
+#[test]
+fn shows_version_restrictions() -> Result<(), Error> {
+    let mut dh = DummyhttpProcess::new(vec!["--http1-only", "--reject-http10"])?;
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    assert!(output.contains("(HTTP/1 only, rejecting HTTP/1.0)"));
+
+    Ok(())
+}
+
+/// With --log-format json, every exchange is a single JSON object and nothing else is printed.
+#[test]
+fn logs_json() -> Result<(), Error> {
+    let mut dh = DummyhttpProcess::new(vec!["--log-format", "json", "-vv", "-b", "teststring"])?;
+
+    Client::new()
+        .post(format!("{}/some/path", dh.url))
+        .body("some body")
+        .send()?
+        .error_for_status()?;
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    let lines = output.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 1);
+    let entry: serde_json::Value = serde_json::from_str(lines[0])?;
+    assert_eq!(entry["request"]["method"], "POST");
+    assert_eq!(entry["request"]["uri"], "/some/path");
+    assert_eq!(entry["request"]["headers"]["content-length"], "9");
+    assert_eq!(entry["request"]["body"], "some body");
+    assert_eq!(entry["response"]["status"], 200);
+    assert_eq!(entry["response"]["body"], "teststring");
+    assert!(entry["latency_ms"].is_f64());
+
+    Ok(())
+}
+
+/// Logfmt and Apache combined log lines contain the essentials.
+#[rstest(
+    format,
+    expected,
+    case::logfmt("logfmt", "method=GET uri=/some/path version=HTTP/1.1 status=200"),
+    case::combined("combined", "\"GET /some/path HTTP/1.1\" 200 9 \"-\" \"test-agent\"")
+)]
+fn logs_single_lines(format: &'static str, expected: &str) -> Result<(), Error> {
+    let mut dh = DummyhttpProcess::new(vec!["--log-format", format])?;
+
+    Client::new()
+        .get(format!("{}/some/path", dh.url))
+        .header("user-agent", "test-agent")
+        .send()?
+        .error_for_status()?;
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    assert_eq!(output.lines().count(), 1);
+    assert!(output.contains(expected));
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+mod utils;
+
+use reqwest::blocking::Client;
+use rstest::rstest;
+use std::thread;
+use std::time::{Duration, Instant};
+use utils::{DummyhttpProcess, Error};
+
+/// How long each benchmark hammers the server
+const DURATION: Duration = Duration::from_secs(1);
+
+/// Number of clients sending requests concurrently
+const CLIENTS: usize = 4;
+
+/// Send requests from `CLIENTS` threads for `DURATION`, returning how many succeeded
+fn run_clients(url: &str) -> Result<usize, Error> {
+    let handles = (0..CLIENTS)
+        .map(|_| {
+            let url = url.to_string();
+            thread::spawn(move || -> Result<usize, reqwest::Error> {
+                let client = Client::new();
+                let start = Instant::now();
+                let mut requests = 0;
+                while start.elapsed() < DURATION {
+                    client.get(&url).send()?.error_for_status()?.bytes()?;
+                    requests += 1;
+                }
+                Ok(requests)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut total = 0;
+    for handle in handles {
+        total += handle.join().map_err(|_| "Client thread panicked")??;
+    }
+    Ok(total)
+}
+
+/// Print the requests/sec we get with static and templated bodies.
+///
+/// Run with `cargo test --release --test benchmark -- --nocapture` for meaningful numbers.
+#[rstest]
+#[case::static_body("static", "Hello, world!")]
+#[case::templated_body("templated", "{{ uuid() }} {{ now() }} {{ lorem(words=10) }}")]
+fn requests_per_second(#[case] name: &str, #[case] body: &'static str) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(["-q", "-b", body])?;
+
+    let requests = run_clients(&dh.url)?;
+    assert!(requests > 0);
+
+    println!(
+        "{name} body: {:.0} requests/sec",
+        requests as f64 / DURATION.as_secs_f64()
+    );
+
+    Ok(())
+}
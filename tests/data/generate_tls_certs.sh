@@ -1,2 +1,4 @@
 #!/usr/bin/env bash
 openssl req -subj '/CN=localhost' -x509 -newkey rsa:4096 -keyout key.pem -out cert.pem -nodes -days 3650
+openssl req -subj '/CN=sni.test' -x509 -newkey rsa:4096 -keyout sni_key.pem -out sni_cert.pem -nodes -days 3650
+openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out ec_key.pem
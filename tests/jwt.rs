@@ -0,0 +1,101 @@
+mod utils;
+
+use aws_lc_rs::{
+    rsa::PublicKeyComponents,
+    signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED, RSA_PKCS1_2048_8192_SHA256},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::blocking::Client;
+use rstest::rstest;
+use sha2::Sha256;
+use utils::{DummyhttpProcess, Error};
+
+/// Split a JWT into its decoded header and claims, the signing input and the signature
+fn decode(token: &str) -> Result<(serde_json::Value, serde_json::Value, String, Vec<u8>), Error> {
+    let parts = token.split('.').collect::<Vec<_>>();
+    assert_eq!(parts.len(), 3);
+    let header = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts[0])?)?;
+    let claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(parts[1])?)?;
+    let signature = URL_SAFE_NO_PAD.decode(parts[2])?;
+    Ok((
+        header,
+        claims,
+        format!("{}.{}", parts[0], parts[1]),
+        signature,
+    ))
+}
+
+/// HS256 tokens are signed with the given secret.
+#[test]
+fn mints_hs256_tokens() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "-b",
+        r#"{{ jwt(claims={"sub": "1234", "admin": true}, key="secret") }}"#,
+    ])?;
+
+    let token = Client::new()
+        .get(&dh.url)
+        .send()?
+        .error_for_status()?
+        .text()?;
+    let (header, claims, signing_input, signature) = decode(&token)?;
+
+    assert_eq!(header, serde_json::json!({"alg": "HS256", "typ": "JWT"}));
+    assert_eq!(claims, serde_json::json!({"sub": "1234", "admin": true}));
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"secret")?;
+    mac.update(signing_input.as_bytes());
+    mac.verify_slice(&signature)?;
+
+    Ok(())
+}
+
+/// RS256/ES256 tokens are signed with --jwt-key and verify against the served JWKS.
+#[rstest]
+#[case::rs256("tests/data/key.pem", "RS256")]
+#[case::es256("tests/data/ec_key.pem", "ES256")]
+fn mints_tokens_matching_jwks(#[case] key: &'static str, #[case] alg: &str) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "--jwt-key",
+        key,
+        "--jwks-path",
+        "/.well-known/jwks.json",
+        "-b",
+        r#"{{ jwt(claims={"sub": "1234"}) }}"#,
+    ])?;
+
+    let client = Client::new();
+    let token = client.get(&dh.url).send()?.error_for_status()?.text()?;
+    let jwks = client
+        .get(format!("{}/.well-known/jwks.json", dh.url))
+        .send()?
+        .error_for_status()?
+        .text()?;
+    let jwks = serde_json::from_str::<serde_json::Value>(&jwks)?;
+
+    let (header, claims, signing_input, signature) = decode(&token)?;
+    let jwk = &jwks["keys"][0];
+    assert_eq!(header["alg"], alg);
+    assert_eq!(jwk["alg"], alg);
+    assert_eq!(header["kid"], jwk["kid"]);
+    assert_eq!(claims, serde_json::json!({"sub": "1234"}));
+
+    let param = |name: &str| URL_SAFE_NO_PAD.decode(jwk[name].as_str().unwrap());
+    if alg == "RS256" {
+        PublicKeyComponents {
+            n: param("n")?,
+            e: param("e")?,
+        }
+        .verify(
+            &RSA_PKCS1_2048_8192_SHA256,
+            signing_input.as_bytes(),
+            &signature,
+        )?;
+    } else {
+        let point = [vec![0x04], param("x")?, param("y")?].concat();
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, point)
+            .verify(signing_input.as_bytes(), &signature)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,254 @@
+mod utils;
+
+use assert_cmd::prelude::*;
+use axum::http::StatusCode;
+use predicates::str::contains;
+use reqwest::blocking::{Client, ClientBuilder};
+use std::fs;
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use utils::Error;
+
+/// Find a port nobody is listening on right now
+fn free_local_port() -> Result<u16, Error> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?.port())
+}
+
+fn is_reachable(address: &String) -> bool {
+    TcpStream::connect(address).is_ok()
+}
+
+/// Spawn dummyhttp with the given arguments and wait for `addresses` to become reachable.
+fn spawn_listening(args: &[String], addresses: &[String]) -> Result<Child, Error> {
+    let child = Command::cargo_bin("dummyhttp")?
+        .args(args)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let start_wait = Instant::now();
+    while start_wait.elapsed().as_secs() < 1 && !addresses.iter().all(is_reachable) {
+        sleep(Duration::from_millis(100));
+    }
+
+    Ok(child)
+}
+
+/// We can listen on IPv4 and IPv6 on the same port and every address shows up in the banner.
+#[test]
+fn listens_on_multiple_addresses() -> Result<(), Error> {
+    let port = free_local_port()?;
+    let mut child = spawn_listening(
+        &[
+            "--listen".into(),
+            format!("http://127.0.0.1:{port}"),
+            "--listen".into(),
+            format!("http://[::1]:{port}"),
+        ],
+        &[format!("127.0.0.1:{port}"), format!("[::1]:{port}")],
+    )?;
+
+    let client = Client::new();
+    for url in [
+        format!("http://127.0.0.1:{port}"),
+        format!("http://[::1]:{port}"),
+    ] {
+        let resp = client.get(url).send()?;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.text()?, "dummyhttp");
+    }
+
+    child.kill()?;
+    let mut output = String::new();
+    child.stdout.as_mut().unwrap().read_to_string(&mut output)?;
+
+    assert!(output.contains(&format!("http://127.0.0.1:{port}")));
+    assert!(output.contains(&format!("http://[::1]:{port}")));
+
+    Ok(())
+}
+
+/// Plain and TLS listeners can be mixed.
+#[test]
+fn listens_on_http_and_https() -> Result<(), Error> {
+    let http_port = free_local_port()?;
+    let https_port = free_local_port()?;
+    let mut child = spawn_listening(
+        &[
+            "--listen".into(),
+            format!("http://127.0.0.1:{http_port}"),
+            "--listen".into(),
+            format!("https://127.0.0.1:{https_port}"),
+            "--tls-cert".into(),
+            "tests/data/cert.pem".into(),
+            "--tls-key".into(),
+            "tests/data/key.pem".into(),
+        ],
+        &[
+            format!("127.0.0.1:{http_port}"),
+            format!("127.0.0.1:{https_port}"),
+        ],
+    )?;
+
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    for url in [
+        format!("http://127.0.0.1:{http_port}"),
+        format!("https://127.0.0.1:{https_port}"),
+    ] {
+        let resp = client.get(url).send()?;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    child.kill()?;
+
+    Ok(())
+}
+
+/// An https:// listener without a certificate is an error.
+#[test]
+fn https_listen_requires_cert() -> Result<(), Error> {
+    Command::cargo_bin("dummyhttp")?
+        .args(["--listen", "https://127.0.0.1:0"])
+        .assert()
+        .failure()
+        .stderr(contains("Error: Listening on https:// requires --tls-cert"));
+
+    Ok(())
+}
+
+/// Listen addresses need a known scheme.
+#[test]
+fn listen_requires_scheme() -> Result<(), Error> {
+    Command::cargo_bin("dummyhttp")?
+        .args(["--listen", "127.0.0.1:8080"])
+        .assert()
+        .failure()
+        .stderr(contains("Unknown scheme"));
+
+    Ok(())
+}
+
+/// We can serve on a Unix socket with the given permissions, replacing a stale socket file, and
+/// log the peer's credentials.
+#[cfg(unix)]
+#[test]
+fn listens_on_unix_socket() -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    let dir = tempfile::tempdir()?;
+    let socket = dir.path().join("dummyhttp.sock");
+    // Simulate a socket file left behind by a crashed instance.
+    drop(UnixListener::bind(&socket)?);
+
+    let mut child = Command::cargo_bin("dummyhttp")?
+        .args([
+            "--listen".into(),
+            format!("unix:{}", socket.to_string_lossy()),
+        ])
+        .args(["--unix-socket-mode", "600"])
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let client = ClientBuilder::new().unix_socket(socket.clone()).build()?;
+    let start_wait = Instant::now();
+    let resp = loop {
+        match client.get("http://localhost/").send() {
+            Ok(resp) => break resp,
+            Err(_) if start_wait.elapsed().as_secs() < 1 => sleep(Duration::from_millis(100)),
+            Err(e) => return Err(e.into()),
+        }
+    };
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.text()?, "dummyhttp");
+    assert_eq!(fs::metadata(&socket)?.permissions().mode() & 0o777, 0o600);
+
+    child.kill()?;
+    let mut output = String::new();
+    child.stdout.as_mut().unwrap().read_to_string(&mut output)?;
+
+    assert!(output.contains(&format!("unix:{}", socket.to_string_lossy())));
+    assert!(output.contains(&format!("pid={}", std::process::id())));
+
+    Ok(())
+}
+
+/// We refuse to replace files which aren't sockets.
+#[cfg(unix)]
+#[test]
+fn unix_socket_keeps_other_files() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("not-a-socket");
+    fs::write(&path, "important")?;
+
+    Command::cargo_bin("dummyhttp")?
+        .args([
+            "--listen".into(),
+            format!("unix:{}", path.to_string_lossy()),
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("exists and is not a socket"));
+    assert_eq!(fs::read_to_string(&path)?, "important");
+
+    Ok(())
+}
+
+/// Sockets passed via systemd socket activation are used instead of binding ourselves.
+#[cfg(unix)]
+#[test]
+fn uses_socket_activation() -> Result<(), Error> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::process::CommandExt;
+
+    let plain = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let tls = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let plain_fd = plain.as_raw_fd();
+    let tls_fd = tls.as_raw_fd();
+
+    // systemd sets LISTEN_PID after forking so we do the same using a shell which then execs us.
+    let mut command = Command::new("sh");
+    command
+        .args(["-c", "LISTEN_PID=$$ exec \"$0\" \"$@\""])
+        .arg(assert_cmd::cargo::cargo_bin("dummyhttp"))
+        .args(["--tls-cert", "tests/data/cert.pem"])
+        .args(["--tls-key", "tests/data/key.pem"])
+        .env("LISTEN_FDS", "2")
+        .env("LISTEN_FDNAMES", "http:https")
+        .stdout(Stdio::piped());
+    // SAFETY: fcntl and dup2 are async-signal-safe.
+    unsafe {
+        command.pre_exec(move || {
+            // Move the sockets out of the way first as they might already occupy fds 3 and 4.
+            let fds = [plain_fd, tls_fd].map(|fd| libc::fcntl(fd, libc::F_DUPFD, 100));
+            for (fd, target) in fds.into_iter().zip([3, 4]) {
+                if fd == -1 || libc::dup2(fd, target) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let plain_url = format!("http://{}", plain.local_addr()?);
+    let tls_url = format!("https://{}", tls.local_addr()?);
+    assert_eq!(client.get(&plain_url).send()?.status(), StatusCode::OK);
+    assert_eq!(client.get(&tls_url).send()?.status(), StatusCode::OK);
+
+    child.kill()?;
+    let mut output = String::new();
+    child.stdout.as_mut().unwrap().read_to_string(&mut output)?;
+
+    assert!(output.contains(&plain_url));
+    assert!(output.contains(&tls_url));
+
+    Ok(())
+}
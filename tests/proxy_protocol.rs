@@ -0,0 +1,113 @@
+mod utils;
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use url::Url;
+use utils::{DummyhttpProcess, Error};
+
+/// Send `prefix` followed by a plain GET request, returning the raw response
+fn send_request(dh: &DummyhttpProcess, prefix: &[u8]) -> Result<String, Error> {
+    let port = Url::parse(&dh.url)?.port().ok_or("No port in url")?;
+    let mut stream = TcpStream::connect(("localhost", port))?;
+    stream.write_all(prefix)?;
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")?;
+
+    let mut response = String::new();
+    // A dropped connection shows up as a reset on some platforms, which is just as good as EOF.
+    let _ = stream.read_to_string(&mut response);
+    Ok(response)
+}
+
+/// Kill dummyhttp and return everything it logged
+fn output(mut dh: DummyhttpProcess) -> Result<String, Error> {
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .take()
+        .unwrap()
+        .read_to_string(&mut output)?;
+    Ok(output)
+}
+
+/// The client address from a v1 header is logged instead of the proxy's.
+#[test]
+fn logs_address_from_v1_header() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(["--proxy-protocol", "required"])?;
+
+    let response = send_request(&dh, b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n")?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+    assert!(output(dh)?.contains("192.0.2.1:56324"));
+
+    Ok(())
+}
+
+/// Templates see the client address from the header as well.
+#[test]
+fn templates_address_from_v1_header() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new([
+        "--proxy-protocol",
+        "required",
+        "-b",
+        "{{ request.client }} {{ request.client_ip }}",
+    ])?;
+
+    let response = send_request(&dh, b"PROXY TCP4 192.0.2.1 192.0.2.2 56324 443\r\n")?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with("\r\n\r\n192.0.2.1:56324 192.0.2.1"));
+
+    Ok(())
+}
+
+/// The client address from a binary v2 header is logged instead of the proxy's.
+#[test]
+fn logs_address_from_v2_header() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(["--proxy-protocol", "required"])?;
+
+    let mut header = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    // Version 2, PROXY command, TCP over IPv6, 36 bytes of addresses
+    header.extend([0x21, 0x21, 0x00, 0x24]);
+    header.extend("2001:db8::1".parse::<std::net::Ipv6Addr>()?.octets());
+    header.extend("2001:db8::2".parse::<std::net::Ipv6Addr>()?.octets());
+    header.extend(56324u16.to_be_bytes());
+    header.extend(443u16.to_be_bytes());
+
+    let response = send_request(&dh, &header)?;
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+    assert!(output(dh)?.contains("[2001:db8::1]:56324"));
+
+    Ok(())
+}
+
+/// Connections without a header are served in optional mode and dropped in required mode.
+#[test]
+fn requires_header_only_when_asked_to() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(["--proxy-protocol", "optional"])?;
+    assert!(send_request(&dh, b"")?.starts_with("HTTP/1.1 200 OK"));
+
+    let dh = DummyhttpProcess::new(["--proxy-protocol", "required"])?;
+    assert_eq!(send_request(&dh, b"")?, "");
+
+    Ok(())
+}
+
+/// Connections stalling in the middle of what might be a header are dropped.
+#[test]
+fn drops_stalled_headers() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(["--proxy-protocol", "optional"])?;
+    let port = Url::parse(&dh.url)?.port().ok_or("No port in url")?;
+    let mut stream = TcpStream::connect(("localhost", port))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    stream.write_all(b"PROXY")?;
+
+    let start = Instant::now();
+    let mut response = String::new();
+    let _ = stream.read_to_string(&mut response);
+    assert_eq!(response, "");
+    assert!(start.elapsed() < Duration::from_secs(8));
+
+    Ok(())
+}
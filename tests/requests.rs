@@ -1,10 +1,14 @@
 mod utils;
 
-use axum::http::{self, Method, StatusCode};
+use axum::http::{self, Method, StatusCode, Version};
 use chrono::DateTime;
 use reqwest::blocking::Client;
 use rstest::rstest;
 use rstest_reuse::{self, apply, template};
+use std::fs;
+use std::io::Read;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 use utils::{DummyhttpProcess, Error};
 use uuid::Uuid;
 
@@ -66,6 +70,288 @@ fn returns_templated_body(method: Method) -> Result<(), Error> {
     Ok(())
 }
 
+/// Fake data functions generate data of the right shape.
+#[rstest]
+#[case::email("{{ email() }}", |s: &str| s.contains('@') && s.contains('.'))]
+#[case::ipv4("{{ ipv4() }}", |s: &str| s.parse::<std::net::Ipv4Addr>().is_ok())]
+#[case::ipv6("{{ ipv6() }}", |s: &str| s.parse::<std::net::Ipv6Addr>().is_ok())]
+#[case::url("{{ url() }}", |s: &str| s.starts_with("https://"))]
+#[case::iban(r#"{{ iban(country="ch") }}"#, |s: &str| s.starts_with("CH") && s.len() == 21)]
+#[case::date_between(
+    r#"{{ date_between(start="2020-02-01", end="2020-02-29") }}"#,
+    |s: &str| s.starts_with("2020-02-") && s.len() == 10
+)]
+#[case::int("{{ int(min=3, max=5) }}", |s: &str| ["3", "4", "5"].contains(&s))]
+#[case::float("{{ float(min=1, max=2) }}", |s: &str| (1.0..=2.0).contains(&s.parse::<f64>().unwrap()))]
+#[case::pick(r#"{{ pick(list=["a", "b"]) }}"#, |s: &str| ["a", "b"].contains(&s))]
+#[case::regex(
+    r#"{{ regex(pattern="[A-Z]{3}-\\d{4}") }}"#,
+    |s: &str| s.len() == 8 && s[..3].chars().all(|c| c.is_ascii_uppercase()) && s[4..].chars().all(|c| c.is_ascii_digit())
+)]
+fn returns_fake_data(
+    #[case] body: &'static str,
+    #[case] is_valid: fn(&str) -> bool,
+) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["-b", body])?;
+
+    for _ in 0..5 {
+        let body = Client::new()
+            .get(&dh.url)
+            .send()?
+            .error_for_status()?
+            .text()?;
+        assert!(is_valid(&body), "unexpected fake data: {body}");
+    }
+
+    Ok(())
+}
+
+/// With --seed, fake data is the same on every run.
+#[test]
+fn seeds_fake_data() -> Result<(), Error> {
+    let body = "{{ name() }} {{ email() }} {{ int() }}";
+    let fetch = |seed: &'static str| -> Result<Vec<String>, Error> {
+        let dh = DummyhttpProcess::new(vec!["-b", body, "--seed", seed])?;
+        (0..3)
+            .map(|_| Ok(Client::new().get(&dh.url).send()?.text()?))
+            .collect()
+    };
+
+    assert_eq!(fetch("42")?, fetch("42")?);
+    assert_ne!(fetch("42")?, fetch("43")?);
+
+    Ok(())
+}
+
+/// With --seed and --fixed-time, random data and the time are the same on every run.
+#[test]
+fn deterministic_with_seed_and_fixed_time() -> Result<(), Error> {
+    let fetch = || -> Result<Vec<(String, String)>, Error> {
+        let dh = DummyhttpProcess::new(vec![
+            "-b",
+            "{{ uuid() }} {{ now() }}",
+            "--seed",
+            "7",
+            "--fixed-time",
+            "2024-05-01T12:00:00+02:00",
+        ])?;
+        (0..3)
+            .map(|_| {
+                let resp = Client::new().get(&dh.url).send()?;
+                let date = resp.headers()["date"].to_str()?.to_string();
+                Ok((date, resp.text()?))
+            })
+            .collect()
+    };
+
+    let responses = fetch()?;
+    assert_eq!(responses, fetch()?);
+    let (date, body) = &responses[0];
+    assert_eq!(DateTime::parse_from_rfc2822(date)?.timestamp(), 1714557600);
+    assert!(body.ends_with(" 2024-05-01T10:00:00+00:00"));
+    assert_ne!(responses[0].1, responses[1].1);
+
+    Ok(())
+}
+
+/// --clock-offset shifts the time templates see.
+#[test]
+fn shifts_clock() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["-b", "{{ now() }}", "--clock-offset", "-1d"])?;
+
+    let body = Client::new().get(&dh.url).send()?.text()?;
+    let offset = chrono::Utc::now().fixed_offset() - DateTime::parse_from_rfc3339(&body)?;
+    assert!((offset.num_seconds() - 86400).abs() < 60);
+
+    Ok(())
+}
+
+/// Encoding, hashing and signing filters produce the expected output.
+#[rstest]
+#[case::base64(r#"{{ "héllo?" | base64_encode }}"#, "aMOpbGxvPw==")]
+#[case::base64_decode(r#"{{ "aMOpbGxvPw==" | base64_decode }}"#, "héllo?")]
+#[case::base64url(r#"{{ "héllo?" | base64url_encode }}"#, "aMOpbGxvPw")]
+#[case::hex(r#"{{ "hi" | hex_encode }}"#, "6869")]
+#[case::urlencode(r#"{{ "a b&c/é" | urlencode }}"#, "a%20b%26c%2F%C3%A9")]
+#[case::json_escape(r#"{{ 'say "hi"' | json_escape }}"#, r#"say \"hi\""#)]
+#[case::sha1(r#"{{ "abc" | sha1 }}"#, "a9993e364706816aba3e25717850c26c9cd0d89d")]
+#[case::sha256(
+    r#"{{ "abc" | sha256 }}"#,
+    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+)]
+#[case::hmac_sha256(
+    r#"sha256={{ "abc" | hmac_sha256(key="secret") }}"#,
+    "sha256=9946dad4e00e913fc8be8e5d3f7e110a4a9e832f83fb09c345285d78638d8a0e"
+)]
+#[case::hmac_sha256_base64(
+    r#"{{ "abc" | hmac_sha256(key="secret", encoding="base64") }}"#,
+    "mUba1OAOkT/Ivo5dP34RCkqegy+D+wnDRShdeGONig4="
+)]
+fn applies_filters(#[case] body: &'static str, #[case] expected: &str) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["-b", body])?;
+
+    let resp = Client::new().get(&dh.url).send()?.error_for_status()?;
+    assert_eq!(resp.text()?, expected);
+
+    Ok(())
+}
+
+/// env() falls back to its default and file() inlines the file as is.
+#[rstest]
+#[case::env_default(r#"{{ env(name="DUMMYHTTP_UNSET", default="fallback") }}"#, "fallback")]
+#[case::file(
+    r#"{{ file(path="tests/data/templates/partial.json") }}"#,
+    "{\"id\": {{ counter() }}}\n"
+)]
+fn returns_env_and_file(#[case] body: &'static str, #[case] expected: &str) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["-b", body])?;
+
+    let resp = Client::new().get(&dh.url).send()?.error_for_status()?;
+    assert_eq!(resp.text()?, expected);
+
+    Ok(())
+}
+
+/// Counters count up separately per name and, if asked to, per request path.
+#[test]
+fn counts_requests() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "-b",
+        r#"{{ counter() }} {{ counter(name="a") }} {{ counter(per_path=true) }}"#,
+    ])?;
+
+    let client = Client::new();
+    let get = |path: &str| -> Result<String, Error> {
+        Ok(client
+            .get(format!("{}{path}", dh.url))
+            .send()?
+            .error_for_status()?
+            .text()?)
+    };
+    assert_eq!(get("/x")?, "1 1 1");
+    assert_eq!(get("/x")?, "2 2 2");
+    assert_eq!(get("/y")?, "3 3 1");
+
+    Ok(())
+}
+
+/// Body files can include templates from --templates-dir, which get rendered as well.
+#[test]
+fn includes_templates() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let body = dir.path().join("body.json");
+    fs::write(&body, r#"[{% include "partial.json" %}]"#)?;
+
+    let dh = DummyhttpProcess::new(vec![
+        "--templates-dir".into(),
+        "tests/data/templates".into(),
+        "--body-file".into(),
+        body.to_string_lossy().to_string(),
+    ])?;
+
+    let client = Client::new();
+    let resp = client.get(&dh.url).send()?.error_for_status()?;
+    assert_eq!(resp.text()?, "[{\"id\": 1}\n]");
+    let resp = client.get(&dh.url).send()?.error_for_status()?;
+    assert_eq!(resp.text()?, "[{\"id\": 2}\n]");
+
+    Ok(())
+}
+
+/// Templates in --templates-dir can extend each other and use each other's components.
+#[test]
+fn returns_body_template() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "--templates-dir",
+        "tests/data/templates",
+        "--body-template",
+        "users/list.json",
+    ])?;
+
+    let resp = Client::new()
+        .get(format!("{}/?page=2", dh.url))
+        .send()?
+        .error_for_status()?;
+    assert_eq!(
+        resp.text()?,
+        "{\"data\": [{\"id\": 1}, {\"id\": 2}], \"page\": 2}\n"
+    );
+
+    Ok(())
+}
+
+/// Changing the files in --templates-dir makes the server pick up the new templates while broken
+/// ones are ignored. Messages about that don't end up in structured logs.
+#[test]
+fn reloads_changed_templates() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let body = dir.path().join("body.txt");
+    fs::write(&body, "old")?;
+
+    let mut dh = DummyhttpProcess::new(vec![
+        "--log-format".into(),
+        "json".into(),
+        "--templates-dir".into(),
+        dir.path().to_string_lossy().to_string(),
+        "--body-template".into(),
+        "body.txt".into(),
+    ])?;
+    let client = Client::new();
+    let wait_for_body = |expected: &str| -> Result<bool, Error> {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if client.get(&dh.url).send()?.text()? == expected {
+                return Ok(true);
+            }
+            sleep(Duration::from_millis(100));
+        }
+        Ok(false)
+    };
+    assert!(wait_for_body("old")?);
+
+    fs::write(&body, "{{ 1 + 1 }}")?;
+    assert!(wait_for_body("2")?);
+
+    fs::write(&body, "{{ broken")?;
+    sleep(Duration::from_secs(1));
+    assert!(wait_for_body("2")?);
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+    assert!(output.lines().count() > 3);
+    for line in output.lines() {
+        serde_json::from_str::<serde_json::Value>(line)?;
+    }
+
+    Ok(())
+}
+
+/// Templates failing to render result in an error response explaining why.
+#[rstest]
+#[case(&[], StatusCode::INTERNAL_SERVER_ERROR)]
+#[case(&["--template-error-code", "503"], StatusCode::SERVICE_UNAVAILABLE)]
+fn returns_template_errors(
+    #[case] extra_args: &[&'static str],
+    #[case] expected_status: StatusCode,
+) -> Result<(), Error> {
+    let mut args = vec!["-b", "{{ 1 / 0 }}"];
+    args.extend(extra_args);
+    let dh = DummyhttpProcess::new(args)?;
+
+    let resp = Client::new().get(&dh.url).send()?;
+
+    assert_eq!(resp.status(), expected_status);
+    let body = resp.text()?;
+    assert!(body.starts_with("Failed to render the body template: Cannot divide by 0"));
+
+    Ok(())
+}
+
 /// Setting a custom code will always answer with that code.
 #[apply(http_methods)]
 fn returns_custom_code(method: Method) -> Result<(), Error> {
@@ -95,6 +381,119 @@ fn returns_custom_headers(method: Method) -> Result<(), Error> {
     Ok(())
 }
 
+/// The status code can be chosen by the request.
+#[rstest]
+#[case("/?status=404", StatusCode::NOT_FOUND)]
+#[case("/?status=201&status=500", StatusCode::CREATED)]
+#[case("/", StatusCode::OK)]
+#[case("/?status=abc", StatusCode::INTERNAL_SERVER_ERROR)]
+#[case("/?status=1000", StatusCode::INTERNAL_SERVER_ERROR)]
+fn returns_templated_code(
+    #[case] path: &str,
+    #[case] expected_status: StatusCode,
+) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "-c",
+        "{{ request.query.status | default(value=200) }}",
+    ])?;
+
+    let resp = Client::new().get(format!("{}{path}", dh.url)).send()?;
+
+    assert_eq!(resp.status(), expected_status);
+    if expected_status == StatusCode::INTERNAL_SERVER_ERROR {
+        assert!(resp
+            .text()?
+            .starts_with("Failed to render the status code template"));
+    }
+
+    Ok(())
+}
+
+/// Header values are rendered per request just like the body.
+#[test]
+fn returns_templated_headers() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "-H",
+        "location:/items/{{ uuid() }}",
+        "-H",
+        "x-echo:{{ request.method }} {{ request.path }} {{ request.headers[\"x-test\"] | default(value=\"none\") }}",
+        "-b",
+        "{{ request.body }}",
+    ])?;
+
+    let client = Client::new();
+    let resp = client
+        .post(format!("{}/some/path", dh.url))
+        .header("x-test", "value")
+        .body("hello")
+        .send()?;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    let location = resp.headers()["location"].to_str()?.to_string();
+    let uuid = location.strip_prefix("/items/").unwrap();
+    assert!(Uuid::parse_str(uuid).is_ok());
+    assert_eq!(resp.headers()["x-echo"], "POST /some/path value");
+    assert_eq!(resp.text()?, "hello");
+
+    let resp = client.get(&dh.url).send()?;
+    assert_ne!(resp.headers()["location"].to_str()?, location);
+    assert_eq!(resp.headers()["x-echo"], "GET / none");
+
+    Ok(())
+}
+
+/// Header templates can refer to the rendered body, e.g. to sign it.
+#[test]
+fn signs_body_in_header() -> Result<(), Error> {
+    use hmac::{Hmac, KeyInit, Mac};
+    use sha2::Sha256;
+
+    let dh = DummyhttpProcess::new(vec![
+        "-b",
+        "{\"id\": \"{{ uuid() }}\"}",
+        "-H",
+        "x-signature:sha256={{ body | hmac_sha256(key=\"secret\") }}",
+    ])?;
+
+    let resp = reqwest::blocking::get(&dh.url)?.error_for_status()?;
+    let signature = resp.headers()["x-signature"].to_str()?.to_string();
+    let body = resp.text()?;
+
+    let mut mac = <Hmac<Sha256> as KeyInit>::new_from_slice(b"secret")?;
+    mac.update(body.as_bytes());
+    let expected = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    assert_eq!(signature, format!("sha256={expected}"));
+
+    Ok(())
+}
+
+/// Header values can contain colons and have surrounding whitespace dropped.
+#[rstest]
+#[case::absolute_url(
+    "location:http://host:8080/items",
+    "location",
+    "http://host:8080/items"
+)]
+#[case::template_with_colon(r#"x-time:{{ "12" ~ ":" ~ "30" }}"#, "x-time", "12:30")]
+#[case::whitespace("x-sig:  sha256=abc ", "x-sig", "sha256=abc")]
+fn returns_headers_with_colons(
+    #[case] header: &'static str,
+    #[case] name: &str,
+    #[case] expected: &str,
+) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["-H", header])?;
+
+    let resp = Client::new().get(&dh.url).send()?.error_for_status()?;
+    assert_eq!(resp.headers()[name], expected);
+
+    Ok(())
+}
+
 /// Setting a custom delay will delay the response making it at least that long.
 #[apply(http_methods)]
 fn returns_custom_delay(method: Method) -> Result<(), Error> {
@@ -111,3 +510,302 @@ fn returns_custom_delay(method: Method) -> Result<(), Error> {
 
     Ok(())
 }
+
+/// With --http1-only, HTTP/2 with prior knowledge is refused.
+#[test]
+fn http1_only_refuses_http2() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["--http1-only"])?;
+
+    let resp = Client::new().get(&dh.url).send()?;
+    assert_eq!(resp.version(), Version::HTTP_11);
+
+    let h2_client = Client::builder().http2_prior_knowledge().build()?;
+    assert!(h2_client.get(&dh.url).send().is_err());
+
+    Ok(())
+}
+
+/// With --http2-only, plain HTTP/2 with prior knowledge works but HTTP/1 is refused.
+#[test]
+fn http2_only_refuses_http1() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["--http2-only"])?;
+
+    let h2_client = Client::builder().http2_prior_knowledge().build()?;
+    let resp = h2_client.get(&dh.url).send()?;
+    assert_eq!(resp.version(), Version::HTTP_2);
+    assert_eq!(resp.text()?, "dummyhttp");
+
+    assert!(Client::new().get(&dh.url).send().is_err());
+
+    Ok(())
+}
+
+/// With --reject-http10, HTTP/1.0 requests get a 505.
+#[test]
+fn rejects_http10() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["--reject-http10"])?;
+
+    let client = Client::new();
+    let resp = client.get(&dh.url).version(Version::HTTP_10).send()?;
+    assert_eq!(resp.status(), StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+
+    let resp = client.get(&dh.url).send()?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+/// With --compress, the encoding is negotiated with the client unless one is forced.
+#[rstest(
+    compress,
+    accept_encoding,
+    expected,
+    case::auto_prefers_brotli("", "gzip, deflate, br, zstd", Some("br")),
+    case::auto_honors_quality("", "br;q=0.5, gzip", Some("gzip")),
+    case::auto_wildcard("", "br;q=0, *", Some("zstd")),
+    case::auto_unsupported("", "compress", None),
+    case::forced("deflate", "", Some("deflate"))
+)]
+fn compresses_response(
+    compress: &'static str,
+    accept_encoding: &str,
+    expected: Option<&str>,
+) -> Result<(), Error> {
+    let mut args = vec!["-vv", "--compress"];
+    if !compress.is_empty() {
+        args.push(compress);
+    }
+    let mut dh = DummyhttpProcess::new(args)?;
+
+    let resp = Client::new()
+        .get(&dh.url)
+        .header("accept-encoding", accept_encoding)
+        .send()?;
+    let encoding = resp.headers().get("content-encoding");
+    assert_eq!(encoding.map(|e| e.to_str().unwrap()), expected);
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    // The log shows the encoding and the decompressed body.
+    if let Some(expected) = expected {
+        assert!(output.contains(&format!("Content-Encoding: {expected}")));
+        assert!(output.contains(&format!("Body ({expected}, ")));
+    }
+    assert!(!output.contains("failed to decompress"));
+    assert!(output.contains("│ dummyhttp"));
+
+    Ok(())
+}
+
+/// Generated bodies are compressed as they are streamed and decompress to their full size.
+#[rstest]
+#[case::gzip("gzip")]
+#[case::br("br")]
+#[case::zstd("zstd")]
+fn compresses_generated_body(#[case] compress: &'static str) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "--body-size",
+        "10MB",
+        "--body-pattern",
+        "zeros",
+        "--compress",
+        compress,
+    ])?;
+
+    let resp = Client::new().get(&dh.url).send()?.error_for_status()?;
+    assert_eq!(resp.headers()["content-encoding"], compress);
+    assert!(resp.headers().get("content-length").is_none());
+
+    let compressed = resp.bytes()?;
+    let mut decompressed = Vec::new();
+    match compress {
+        "gzip" => flate2::read::GzDecoder::new(&compressed[..]).read_to_end(&mut decompressed)?,
+        "br" => brotli::Decompressor::new(&compressed[..], 4096).read_to_end(&mut decompressed)?,
+        _ => zstd::stream::Decoder::new(&compressed[..])?.read_to_end(&mut decompressed)?,
+    };
+    assert_eq!(decompressed.len(), 10_000_000);
+    assert!(decompressed.iter().all(|&byte| byte == 0));
+
+    Ok(())
+}
+
+/// With --max-body-size, larger bodies get a 413 whether or not their size is known upfront.
+#[test]
+fn rejects_large_bodies() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["--max-body-size", "1KiB"])?;
+
+    let client = Client::new();
+    let resp = client.post(&dh.url).body(vec![b'a'; 1024]).send()?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let resp = client.post(&dh.url).body(vec![b'a'; 1025]).send()?;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Without a Content-Length, the body is sent chunked and we notice while reading it.
+    let chunked = reqwest::blocking::Body::new(std::io::Cursor::new(vec![b'a'; 4096]));
+    let resp = client.post(&dh.url).body(chunked).send()?;
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    Ok(())
+}
+
+/// Without --max-body-size, bodies of any size are accepted.
+#[test]
+fn accepts_large_bodies() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(Vec::<String>::new())?;
+
+    let resp = Client::new()
+        .post(&dh.url)
+        .body(vec![b'a'; 3 * 1024 * 1024])
+        .send()?;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    Ok(())
+}
+
+/// Bodies above --log-body-limit are counted and hashed and only their beginning is logged.
+#[test]
+fn truncates_logged_bodies() -> Result<(), Error> {
+    use sha2::{Digest, Sha256};
+
+    let mut dh = DummyhttpProcess::new(vec!["-vv", "--log-body-limit", "16"])?;
+
+    let body = (0..1_000_000u32)
+        .map(|i| b'a' + (i % 26) as u8)
+        .collect::<Vec<_>>();
+    let sha256 = Sha256::digest(&body)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    Client::new()
+        .post(&dh.url)
+        .body(body)
+        .send()?
+        .error_for_status()?;
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    assert!(output.contains(&format!("first 16 of 1000000 bytes, sha256 {sha256}")));
+    assert!(output.contains("│ abcdefghijklmnop\n"));
+    assert!(!output.contains("abcdefghijklmnopq"));
+
+    Ok(())
+}
+
+/// The handler gets the whole request body no matter how much of it gets logged.
+#[test]
+fn passes_whole_body_beyond_log_limit() -> Result<(), Error> {
+    use sha2::{Digest, Sha256};
+
+    let dh = DummyhttpProcess::new(vec![
+        "--log-body-limit",
+        "16",
+        "-b",
+        "{{ request.body | length }} {{ request.body | sha256 }}",
+    ])?;
+
+    let body = "a".repeat(100_000);
+    let resp = Client::new()
+        .post(&dh.url)
+        .body(body.clone())
+        .send()?
+        .error_for_status()?;
+
+    let sha256 = Sha256::digest(&body)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    assert_eq!(resp.text()?, format!("100000 {sha256}"));
+
+    Ok(())
+}
+
+/// Templates only get to see the beginning of large request bodies.
+#[test]
+fn truncates_templated_request_body() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "--template-body-limit",
+        "10",
+        "-b",
+        "{{ request.body }}",
+    ])?;
+
+    let resp = Client::new()
+        .post(&dh.url)
+        .body("a".repeat(100))
+        .send()?
+        .error_for_status()?;
+    assert_eq!(resp.text()?, "a".repeat(10));
+
+    Ok(())
+}
+
+/// With --body-size, bodies of the given size are generated according to --body-pattern.
+#[rstest(
+    pattern,
+    case::zeros("zeros"),
+    case::text("text"),
+    case::random("random"),
+    case::seeded("seeded")
+)]
+fn generates_bodies(pattern: &'static str) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "--body-size",
+        "1MB",
+        "--body-pattern",
+        pattern,
+        "-b",
+        "abc",
+    ])?;
+
+    let client = Client::new();
+    let mut bodies = vec![];
+    for _ in 0..2 {
+        let resp = client.get(&dh.url).send()?;
+        assert_eq!(resp.headers()["content-length"], "1000000");
+        let body = resp.bytes()?;
+        assert_eq!(body.len(), 1_000_000);
+        bodies.push(body);
+    }
+
+    match pattern {
+        "zeros" => assert!(bodies[0].iter().all(|&b| b == 0)),
+        "text" => assert!(bodies[0]
+            .iter()
+            .enumerate()
+            .all(|(i, &b)| b == b"abc"[i % 3])),
+        "random" => assert_ne!(bodies[0], bodies[1]),
+        "seeded" => {
+            assert_eq!(bodies[0], bodies[1]);
+            assert!(bodies[0].iter().any(|&b| b != 0));
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// With --chunked, generated bodies are sent without a Content-Length.
+#[test]
+fn generates_chunked_bodies() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec!["--body-size", "100KiB", "--chunked"])?;
+
+    let resp = Client::new().get(&dh.url).send()?;
+    assert_eq!(resp.headers()["transfer-encoding"], "chunked");
+    assert!(resp.headers().get("content-length").is_none());
+    assert_eq!(resp.bytes()?.len(), 100 * 1024);
+
+    Ok(())
+}
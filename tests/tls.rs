@@ -4,7 +4,13 @@ use assert_cmd::prelude::*;
 use axum::http::StatusCode;
 use predicates::str::contains;
 use reqwest::blocking::ClientBuilder;
+use rstest::rstest;
+use std::fs;
+use std::net::SocketAddr;
 use std::process::Command;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use url::Url;
 use utils::{DummyhttpProcess, Error};
 
 /// We can connect to a secured connection.
@@ -55,3 +61,365 @@ fn wrong_path_key() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Returns whether the certificate presented by the server was issued for `common_name`.
+fn presented_cert_has_cn(resp: &reqwest::blocking::Response, common_name: &str) -> bool {
+    let tls_info = resp.extensions().get::<reqwest::tls::TlsInfo>().unwrap();
+    let der = tls_info.peer_certificate().unwrap();
+    der.windows(common_name.len())
+        .any(|window| window == common_name.as_bytes())
+}
+
+/// The certificate is selected by the SNI hostname, falling back to the default one.
+#[rstest]
+#[case("sni.test", "sni.test")]
+#[case("localhost", "localhost")]
+#[case("unknown.test", "localhost")]
+fn tls_sni_selects_cert(#[case] hostname: &str, #[case] expected_cn: &str) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "--tls-cert",
+        "tests/data/cert.pem",
+        "--tls-key",
+        "tests/data/key.pem",
+        "--tls-cert",
+        "sni.test=tests/data/sni_cert.pem,tests/data/sni_key.pem",
+    ])?;
+    let port = Url::parse(&dh.url)?.port().unwrap();
+
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .resolve(hostname, SocketAddr::from(([127, 0, 0, 1], port)))
+        .build()?;
+    let resp = client.get(format!("https://{hostname}:{port}")).send()?;
+
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(presented_cert_has_cn(&resp, expected_cn));
+
+    Ok(())
+}
+
+/// Unknown SNI hostnames abort the handshake if requested.
+#[test]
+fn tls_sni_rejects_unknown() -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "--tls-cert",
+        "sni.test=tests/data/sni_cert.pem,tests/data/sni_key.pem",
+        "--tls-unknown-sni",
+        "reject",
+    ])?;
+    let port = Url::parse(&dh.url)?.port().unwrap();
+
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .resolve("unknown.test", SocketAddr::from(([127, 0, 0, 1], port)))
+        .resolve("sni.test", SocketAddr::from(([127, 0, 0, 1], port)))
+        .build()?;
+
+    assert!(client
+        .get(format!("https://unknown.test:{port}"))
+        .send()
+        .is_err());
+    assert_eq!(
+        client
+            .get(format!("https://sni.test:{port}"))
+            .send()?
+            .status(),
+        StatusCode::OK
+    );
+
+    Ok(())
+}
+
+/// A default certificate needs a key.
+#[test]
+fn default_cert_requires_key() -> Result<(), Error> {
+    Command::cargo_bin("dummyhttp")?
+        .args(["--tls-cert", "tests/data/cert.pem"])
+        .assert()
+        .failure()
+        .stderr(contains(
+            "Error: --tls-cert without a hostname requires --tls-key",
+        ));
+
+    Ok(())
+}
+
+/// Poll the server until it presents a certificate for `common_name` or give up after 5s.
+fn wait_for_cert_cn(url: &str, common_name: &str) -> Result<bool, Error> {
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(5) {
+        // Use a fresh client every time so we do a new handshake.
+        let client = ClientBuilder::new()
+            .danger_accept_invalid_certs(true)
+            .tls_info(true)
+            .build()?;
+        if presented_cert_has_cn(&client.get(url).send()?, common_name) {
+            return Ok(true);
+        }
+        sleep(Duration::from_millis(100));
+    }
+    Ok(false)
+}
+
+/// Replacing the certificate files makes the server pick up the new certificate. Messages about
+/// that don't end up in structured logs.
+#[test]
+fn tls_reloads_changed_cert() -> Result<(), Error> {
+    use std::io::Read;
+
+    let dir = tempfile::tempdir()?;
+    let cert = dir.path().join("cert.pem");
+    let key = dir.path().join("key.pem");
+    fs::copy("tests/data/cert.pem", &cert)?;
+    fs::copy("tests/data/key.pem", &key)?;
+
+    let mut dh = DummyhttpProcess::new(vec![
+        "--log-format".into(),
+        "json".into(),
+        "--tls-cert".into(),
+        cert.to_string_lossy().to_string(),
+        "--tls-key".into(),
+        key.to_string_lossy().to_string(),
+    ])?;
+    assert!(wait_for_cert_cn(&dh.url, "localhost")?);
+
+    fs::copy("tests/data/sni_cert.pem", &cert)?;
+    fs::copy("tests/data/sni_key.pem", &key)?;
+    assert!(wait_for_cert_cn(&dh.url, "sni.test")?);
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+    assert!(output.lines().count() >= 2);
+    for line in output.lines() {
+        serde_json::from_str::<serde_json::Value>(line)?;
+    }
+
+    Ok(())
+}
+
+/// If the new certificate doesn't fit its key, we keep serving the old one.
+#[test]
+fn tls_keeps_old_cert_on_broken_reload() -> Result<(), Error> {
+    let dir = tempfile::tempdir()?;
+    let cert = dir.path().join("cert.pem");
+    let key = dir.path().join("key.pem");
+    fs::copy("tests/data/cert.pem", &cert)?;
+    fs::copy("tests/data/key.pem", &key)?;
+
+    let dh = DummyhttpProcess::new(vec![
+        "--tls-cert".into(),
+        cert.to_string_lossy().to_string(),
+        "--tls-key".into(),
+        key.to_string_lossy().to_string(),
+    ])?;
+
+    fs::copy("tests/data/sni_cert.pem", &cert)?;
+    sleep(Duration::from_secs(1));
+    assert!(wait_for_cert_cn(&dh.url, "localhost")?);
+
+    Ok(())
+}
+
+/// Accepts any server certificate, the HTTP/3 equivalent of `danger_accept_invalid_certs`.
+#[cfg(feature = "http3")]
+#[derive(Debug)]
+struct NoVerifier(std::sync::Arc<rustls::crypto::CryptoProvider>);
+
+#[cfg(feature = "http3")]
+impl rustls::client::danger::ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _message: &[u8],
+        _cert: &rustls::pki_types::CertificateDer<'_>,
+        _dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        Ok(rustls::client::danger::HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// Make a single HTTP/3 request, a POST if there's a body, returning status and body.
+#[cfg(feature = "http3")]
+async fn request_http3(port: u16, body: &[u8]) -> Result<(StatusCode, String), Error> {
+    use bytes::Buf;
+    use std::sync::Arc;
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(NoVerifier(provider)))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![b"h3".to_vec()];
+    let client_config = quinn::ClientConfig::new(Arc::new(
+        quinn::crypto::rustls::QuicClientConfig::try_from(crypto)?,
+    ));
+
+    let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse()?)?;
+    endpoint.set_default_client_config(client_config);
+    let connection = endpoint
+        .connect(SocketAddr::from(([127, 0, 0, 1], port)), "localhost")?
+        .await?;
+
+    let (mut driver, mut send_request) =
+        h3::client::new(h3_quinn::Connection::new(connection)).await?;
+    tokio::spawn(async move { driver.wait_idle().await });
+
+    let method = if body.is_empty() { "GET" } else { "POST" };
+    let req = axum::http::Request::builder()
+        .method(method)
+        .uri(format!("https://localhost:{port}/"))
+        .body(())?;
+    let mut stream = send_request.send_request(req).await?;
+    for chunk in body.chunks(16 * 1024) {
+        // The server stops reading once it has seen enough of a body that's too large.
+        if stream
+            .send_data(bytes::Bytes::copy_from_slice(chunk))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+    let _ = stream.finish().await;
+    let resp = stream.recv_response().await?;
+
+    let mut body = vec![];
+    while let Some(mut chunk) = stream.recv_data().await? {
+        body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+    }
+
+    Ok((resp.status(), String::from_utf8(body)?))
+}
+
+/// With HTTP/3 support, HTTPS listeners advertise and serve HTTP/3 on the same port.
+#[cfg(feature = "http3")]
+#[test]
+fn http3_works() -> Result<(), Error> {
+    use std::io::Read;
+
+    let mut dh = DummyhttpProcess::new(vec![
+        "--tls-cert",
+        "tests/data/cert.pem",
+        "--tls-key",
+        "tests/data/key.pem",
+    ])?;
+    let port = Url::parse(&dh.url)?.port().unwrap();
+
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resp = client.get(&dh.url).send()?;
+    assert_eq!(
+        resp.headers()["alt-svc"],
+        format!("h3=\":{port}\"; ma=86400")
+    );
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    let (status, body) = runtime.block_on(request_http3(port, &[]))?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "dummyhttp");
+
+    dh.child.kill()?;
+    let mut output = String::new();
+    dh.child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_to_string(&mut output)?;
+
+    assert!(output.contains(&format!("quic://0.0.0.0:{port}")));
+    assert!(output.contains("HTTP/3"));
+
+    Ok(())
+}
+
+/// HTTP/3 isn't advertised when we don't listen for it because of a version restriction.
+#[cfg(feature = "http3")]
+#[rstest(flag, case("--http1-only"), case("--http2-only"))]
+fn http3_not_advertised_when_restricted(flag: &'static str) -> Result<(), Error> {
+    let dh = DummyhttpProcess::new(vec![
+        "--tls-cert",
+        "tests/data/cert.pem",
+        "--tls-key",
+        "tests/data/key.pem",
+        flag,
+    ])?;
+
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resp = client.get(&dh.url).send()?;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp.headers().get("alt-svc").is_none());
+
+    Ok(())
+}
+
+/// HTTP/3 request bodies are held to --max-body-size and generated bodies are streamed.
+#[cfg(feature = "http3")]
+#[test]
+fn http3_streams_bodies() -> Result<(), Error> {
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    let dh = DummyhttpProcess::new(vec![
+        "--tls-cert",
+        "tests/data/cert.pem",
+        "--tls-key",
+        "tests/data/key.pem",
+        "--max-body-size",
+        "1KiB",
+        "-b",
+        "{{ request.body | length }}",
+    ])?;
+    let port = Url::parse(&dh.url)?.port().unwrap();
+    let (status, body) = runtime.block_on(request_http3(port, &[b'a'; 1000]))?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "1000");
+    let (status, _) = runtime.block_on(request_http3(port, &vec![b'a'; 1_000_000]))?;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    let dh = DummyhttpProcess::new(vec![
+        "--tls-cert",
+        "tests/data/cert.pem",
+        "--tls-key",
+        "tests/data/key.pem",
+        "--body-size",
+        "5MB",
+    ])?;
+    let port = Url::parse(&dh.url)?.port().unwrap();
+    let (status, body) = runtime.block_on(request_http3(port, &[]))?;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.len(), 5_000_000);
+
+    Ok(())
+}
@@ -1,17 +1,21 @@
 use assert_cmd::prelude::*;
-use port_check::{free_local_port, is_port_reachable};
 use std::ffi::OsStr;
+use std::fs;
 use std::process::{Child, Command, Stdio};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
+use tempfile::TempDir;
 
 /// Error type used by tests
 pub type Error = Box<dyn std::error::Error>;
 
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct DummyhttpProcess {
     pub child: Child,
     pub url: String,
+    /// Holds the ready file, removed once we're done
+    ready_dir: TempDir,
 }
 
 impl Drop for DummyhttpProcess {
@@ -30,24 +34,26 @@ impl DummyhttpProcess {
         I: IntoIterator<Item = S> + Clone + std::fmt::Debug,
         S: AsRef<OsStr> + PartialEq + From<&'static str>,
     {
-        let port = free_local_port()
-            .expect("Couldn't find a free local port")
-            .to_string();
+        let ready_dir = tempfile::tempdir()?;
+        let ready_file = ready_dir.path().join("ready.json");
 
         let child = Command::cargo_bin("dummyhttp")?
-            .arg("-p")
-            .arg(&port)
+            .args(["-p", "0"])
+            .arg("--ready-file")
+            .arg(&ready_file)
             .args(args.clone())
             .stdout(Stdio::piped())
             .spawn()?;
 
-        // Wait a max of 1s for the port to become available.
+        // Wait a max of 1s for dummyhttp to tell us where it's listening.
         let start_wait = Instant::now();
-        while start_wait.elapsed().as_secs() < 1
-            && !is_port_reachable(format!("localhost:{}", port))
-        {
-            sleep(Duration::from_millis(100));
+        while start_wait.elapsed().as_secs() < 1 && !ready_file.exists() {
+            sleep(Duration::from_millis(10));
         }
+        let readiness: serde_json::Value = serde_json::from_str(&fs::read_to_string(&ready_file)?)?;
+        let port = readiness["listeners"][0]["port"]
+            .as_u64()
+            .ok_or("No port in ready file")?;
 
         let proto = if args.into_iter().any(|x| x == "--tls-cert".into()) {
             "https".to_string()
@@ -56,6 +62,10 @@ impl DummyhttpProcess {
         };
         let url = format!("{proto}://localhost:{port}", proto = proto, port = port);
 
-        Ok(Self { child, url })
+        Ok(Self {
+            child,
+            url,
+            ready_dir,
+        })
     }
 }